@@ -0,0 +1,83 @@
+use crate::git;
+use crate::remotes;
+use log::debug;
+use serde_derive::Serialize;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A single line of the audit log
+#[derive(Serialize, Debug)]
+struct AuditEntry<'a> {
+    timestamp: u64,
+    operation: &'a str,
+    remote: Option<String>,
+    id: Option<i64>,
+    outcome: &'a str,
+    error: Option<&'a str>,
+}
+
+/// Format an operation as a line of the audit log
+fn format_entry(
+    timestamp: u64,
+    operation: &str,
+    remote: Option<String>,
+    mr_id: Option<i64>,
+    result: &Result<(), String>,
+) -> String {
+    let entry = AuditEntry {
+        timestamp,
+        operation,
+        remote,
+        id: mr_id,
+        outcome: if result.is_ok() { "success" } else { "failure" },
+        error: result.as_ref().err().map(String::as_str),
+    };
+    format!("{}\n", serde_json::to_string(&entry).unwrap())
+}
+
+/// Append the operation to the audit log, if one is configured. Failing to write is not fatal.
+pub fn record(operation: &str, remote_name: &str, mr_id: Option<i64>, result: &Result<(), String>) {
+    let path = match git::get_config("auditlog") {
+        Some(path) => shellexpand::tilde(&path).to_string(),
+        None => return,
+    };
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default();
+    let remote = git::find_remote_url(remote_name).and_then(|url| remotes::describe_project(&url));
+    let line = format_entry(timestamp, operation, remote, mr_id, result);
+    debug!("Writing audit entry to {}: {}", path, line.trim_end());
+    // A single append-mode write keeps concurrent entries from interleaving
+    let written = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .and_then(|mut file| file.write_all(line.as_bytes()));
+    if let Err(error) = written {
+        eprintln!("Warning: could not write to the audit log {}: {}", path, error);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_entry() {
+        let line = format_entry(
+            1_560_000_000,
+            "checkout",
+            Some(String::from("github.com/my_org/my_project")),
+            Some(42),
+            &Err(String::from("no such branch")),
+        );
+        assert_eq!(
+            "{\"timestamp\":1560000000,\"operation\":\"checkout\",\
+             \"remote\":\"github.com/my_org/my_project\",\"id\":42,\
+             \"outcome\":\"failure\",\"error\":\"no such branch\"}\n",
+            line
+        );
+    }
+}
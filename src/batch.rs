@@ -0,0 +1,111 @@
+/// How many requests a batch can act on before asking for confirmation, unless
+/// `req.confirmthreshold` says otherwise
+pub const DEFAULT_CONFIRM_THRESHOLD: usize = 10;
+
+/// What has to happen before a batch operation goes ahead
+#[derive(Debug, PartialEq)]
+pub enum Confirmation {
+    /// The batch is small enough, or `--yes` was passed
+    NotNeeded,
+    /// Ask the user first
+    Ask(String),
+    /// There's nobody to ask, so don't go ahead
+    Refuse(String),
+}
+
+/// Parse the `req.confirmthreshold` setting
+pub fn confirm_threshold(value: Option<String>) -> Result<usize, String> {
+    match value {
+        None => Ok(DEFAULT_CONFIRM_THRESHOLD),
+        Some(value) => value.trim().parse().map_err(|_| {
+            format!(
+                "req.confirmthreshold should be a number of requests, not {:?}",
+                value
+            )
+        }),
+    }
+}
+
+/// Decide whether acting on `count` requests needs confirming first
+pub fn confirmation(
+    operation: &str,
+    count: usize,
+    threshold: usize,
+    assume_yes: bool,
+    interactive: bool,
+) -> Confirmation {
+    if assume_yes || count <= threshold {
+        Confirmation::NotNeeded
+    } else if interactive {
+        Confirmation::Ask(format!("{} {} requests?", operation, count))
+    } else {
+        Confirmation::Refuse(format!(
+            "Refusing to {} {} requests, more than req.confirmthreshold ({}), without \
+             confirmation. Pass --yes to go ahead",
+            operation.to_lowercase(),
+            count,
+            threshold
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_confirm_threshold() {
+        assert_eq!(Ok(DEFAULT_CONFIRM_THRESHOLD), confirm_threshold(None));
+        assert_eq!(Ok(25), confirm_threshold(Some(String::from(" 25 "))));
+        assert_eq!(Ok(0), confirm_threshold(Some(String::from("0"))));
+        assert!(confirm_threshold(Some(String::from("lots"))).is_err());
+        assert!(confirm_threshold(Some(String::from("-1"))).is_err());
+    }
+
+    #[test]
+    fn test_confirmation_at_threshold() {
+        assert_eq!(
+            Confirmation::NotNeeded,
+            confirmation("Check out", 10, 10, false, false)
+        );
+        assert_eq!(
+            Confirmation::Ask(String::from("Check out 11 requests?")),
+            confirmation("Check out", 11, 10, false, true)
+        );
+    }
+
+    #[test]
+    fn test_confirmation_non_interactive() {
+        match confirmation("Check out", 300, 10, false, false) {
+            Confirmation::Refuse(error) => {
+                assert!(error.contains("check out 300 requests"));
+                assert!(error.contains("--yes"));
+            }
+            other => panic!("expected a refusal, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_confirmation_assume_yes() {
+        assert_eq!(
+            Confirmation::NotNeeded,
+            confirmation("Check out", 300, 10, true, false)
+        );
+        assert_eq!(
+            Confirmation::NotNeeded,
+            confirmation("Check out", 300, 10, true, true)
+        );
+    }
+
+    #[test]
+    fn test_confirmation_zero_threshold() {
+        assert_eq!(
+            Confirmation::NotNeeded,
+            confirmation("Fetch", 0, 0, false, false)
+        );
+        assert!(matches!(
+            confirmation("Fetch", 1, 0, false, true),
+            Confirmation::Ask(_)
+        ));
+    }
+}
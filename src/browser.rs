@@ -0,0 +1,69 @@
+use duct::cmd;
+use log::debug;
+
+/// Check whether there's a display to open a browser on. `var` looks up an environment variable.
+pub fn has_display<F>(var: F) -> bool
+where
+    F: Fn(&str) -> Option<String>,
+{
+    if var("BROWSER").is_some_and(|browser| !browser.is_empty()) {
+        return true;
+    }
+    if cfg!(any(target_os = "macos", target_os = "windows")) {
+        return true;
+    }
+    // Elsewhere, e.g. over SSH or in a container, there's nothing to show a browser on
+    ["DISPLAY", "WAYLAND_DISPLAY"]
+        .iter()
+        .any(|name| var(name).is_some_and(|value| !value.is_empty()))
+}
+
+/// Open the URL in the user's browser, or print it if there's no browser to open
+pub fn open<F>(url: &str, var: F) -> Result<(), String>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    if !has_display(&var) {
+        println!("{}", url);
+        return Ok(());
+    }
+    let expression = match var("BROWSER").filter(|browser| !browser.is_empty()) {
+        Some(browser) => cmd!(browser, url),
+        None if cfg!(target_os = "macos") => cmd!("open", url),
+        None if cfg!(target_os = "windows") => cmd!("cmd", "/C", "start", "", url),
+        None => cmd!("xdg-open", url),
+    };
+    debug!("Opening browser: {:?}", expression);
+    match expression.stdout_null().stderr_null().run() {
+        Ok(_) => {
+            eprintln!("Opened {}", url);
+            Ok(())
+        }
+        Err(error) => {
+            debug!("Couldn't start the browser: {}", error);
+            eprintln!("Couldn't open a browser; the URL is:");
+            println!("{}", url);
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::env;
+
+    #[test]
+    fn test_has_display_browser_override() {
+        assert!(has_display(env(&[("BROWSER", "firefox")])));
+    }
+
+    #[test]
+    #[cfg(not(any(target_os = "macos", target_os = "windows")))]
+    fn test_has_display_headless() {
+        assert!(!has_display(env(&[])));
+        assert!(!has_display(env(&[("DISPLAY", ""), ("BROWSER", "")])));
+        assert!(has_display(env(&[("DISPLAY", ":0")])));
+        assert!(has_display(env(&[("WAYLAND_DISPLAY", "wayland-0")])));
+    }
+}
@@ -0,0 +1,64 @@
+/// Find the ID of the request the current CI job runs for, and the variable it came from.
+/// `var` looks up an environment variable.
+pub fn detect_request_id<F>(var: F) -> Option<(&'static str, i64)>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    // GitLab CI
+    if let Some(mr_id) = var("CI_MERGE_REQUEST_IID").and_then(|iid| iid.trim().parse().ok()) {
+        return Some(("CI_MERGE_REQUEST_IID", mr_id));
+    }
+    // GitHub Actions, e.g. `refs/pull/42/merge`
+    if let Some(mr_id) = var("GITHUB_REF").and_then(|github_ref| parse_github_ref(&github_ref)) {
+        return Some(("GITHUB_REF", mr_id));
+    }
+    // Bitbucket Pipelines
+    if let Some(mr_id) = var("BITBUCKET_PR_ID").and_then(|pr_id| pr_id.trim().parse().ok()) {
+        return Some(("BITBUCKET_PR_ID", mr_id));
+    }
+    None
+}
+
+/// Get the pull request ID from a GitHub Actions ref like `refs/pull/42/merge`
+fn parse_github_ref(github_ref: &str) -> Option<i64> {
+    let mut parts = github_ref.trim().split('/');
+    match (parts.next(), parts.next(), parts.next()) {
+        (Some("refs"), Some("pull"), Some(pr_id)) => pr_id.parse().ok(),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::env;
+
+    #[test]
+    fn test_detect_request_id_gitlab() {
+        let var = env(&[("CI_MERGE_REQUEST_IID", "17")]);
+        assert_eq!(Some(("CI_MERGE_REQUEST_IID", 17)), detect_request_id(var));
+    }
+
+    #[test]
+    fn test_detect_request_id_github() {
+        let var = env(&[("GITHUB_REF", "refs/pull/42/merge")]);
+        assert_eq!(Some(("GITHUB_REF", 42)), detect_request_id(var));
+    }
+
+    #[test]
+    fn test_detect_request_id_github_branch_push() {
+        let var = env(&[("GITHUB_REF", "refs/heads/master")]);
+        assert_eq!(None, detect_request_id(var));
+    }
+
+    #[test]
+    fn test_detect_request_id_bitbucket() {
+        let var = env(&[("BITBUCKET_PR_ID", "9")]);
+        assert_eq!(Some(("BITBUCKET_PR_ID", 9)), detect_request_id(var));
+    }
+
+    #[test]
+    fn test_detect_request_id_outside_ci() {
+        assert_eq!(None, detect_request_id(env(&[])));
+    }
+}
@@ -0,0 +1,56 @@
+use duct::{cmd, Expression};
+use log::debug;
+
+/// Build the command that shows a request's changes, from `base` (its target) to `head`.
+/// Without a `req.difftool` template this is `git diff <base>...<head>`, which pages through
+/// the user's configured pager. A template is run by the shell, with `{base}` and `{head}`
+/// filled in, e.g. `git diff {base}...{head} | delta`.
+pub fn diff_command(template: Option<&str>, base: &str, head: &str) -> Expression {
+    let template = match template.map(str::trim).filter(|template| !template.is_empty()) {
+        Some(template) => template,
+        None => return cmd!("git", "diff", format!("{}...{}", base, head)),
+    };
+    let command = render(template);
+    debug!("Running diff tool: {}", command);
+    let shell = if cfg!(windows) {
+        cmd!("cmd", "/C", command)
+    } else {
+        cmd!("sh", "-c", command)
+    };
+    shell.env("GIT_REQ_BASE", base).env("GIT_REQ_HEAD", head)
+}
+
+/// Fill the placeholders in a diff tool template with references to the environment the
+/// command runs in, so branch names never have to be quoted for the shell
+fn render(template: &str) -> String {
+    let (base, head) = if cfg!(windows) {
+        ("%GIT_REQ_BASE%", "%GIT_REQ_HEAD%")
+    } else {
+        ("\"$GIT_REQ_BASE\"", "\"$GIT_REQ_HEAD\"")
+    };
+    template.replace("{base}", base).replace("{head}", head)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[cfg(not(windows))]
+    fn test_render() {
+        assert_eq!(
+            r#"git diff "$GIT_REQ_BASE"..."$GIT_REQ_HEAD" | delta"#,
+            render("git diff {base}...{head} | delta")
+        );
+        assert_eq!("difft", render("difft"));
+    }
+
+    #[test]
+    #[cfg(not(windows))]
+    fn test_diff_command_template() {
+        let output = diff_command(Some("echo {base} {head}"), "origin/main", "pr/42; rm -rf x")
+            .read()
+            .unwrap();
+        assert_eq!("origin/main pr/42; rm -rf x", output);
+    }
+}
@@ -1,13 +1,14 @@
-use std::path::Path;
+use std::fs;
+use std::path::{Path, PathBuf};
 use std::str;
 
 use duct::cmd;
-use git2::{Config, Error, Repository};
+use git2::{BranchType, Config, Error, Reference, Repository};
 use log::debug;
 use shellexpand;
 
 /// Convert a domain string into a configuration slug
-fn slugify_domain(domain: &str) -> String {
+pub fn slugify_domain(domain: &str) -> String {
     str::replace(domain, ".", "|")
 }
 
@@ -18,6 +19,46 @@ pub fn get_remote_url(remote: &str) -> String {
     String::from(remote.url().unwrap())
 }
 
+/// Get the URL of the given remote, if the repository and remote exist
+pub fn find_remote_url(remote: &str) -> Option<String> {
+    let repo = Repository::open_from_env().ok()?;
+    let remote = repo.find_remote(remote).ok()?;
+    remote.url().map(String::from)
+}
+
+/// Get the push URL (`remote.<name>.pushurl`) of the given remote, if it has one
+pub fn find_remote_push_url(remote: &str) -> Option<String> {
+    let repo = Repository::open_from_env().ok()?;
+    let remote = repo.find_remote(remote).ok()?;
+    remote.pushurl().map(String::from)
+}
+
+/// List the repository's remotes as `(name, url)` pairs
+pub fn list_remotes() -> Vec<(String, String)> {
+    let repo = match Repository::open_from_env() {
+        Ok(repo) => repo,
+        Err(_) => return vec![],
+    };
+    let names = match repo.remotes() {
+        Ok(names) => names,
+        Err(_) => return vec![],
+    };
+    names
+        .iter()
+        .flatten()
+        .filter_map(|name| {
+            let url = repo.find_remote(name).ok()?.url()?.to_string();
+            Some((String::from(name), url))
+        })
+        .collect()
+}
+
+/// Get the root of the repository's working tree, if it has one
+pub fn workdir() -> Option<PathBuf> {
+    let repo = Repository::open_from_env().ok()?;
+    repo.workdir().map(Path::to_path_buf)
+}
+
 /// Get a value fom the repository config
 pub fn get_repo_info(repo_field: &str) -> Result<String, Error> {
     let repo = Repository::open_from_env().expect("Couldn't find repository");
@@ -34,6 +75,21 @@ pub fn get_config(field_name: &str) -> Option<String> {
     }
 }
 
+/// Check whether the given project-local git-req boolean config is enabled
+pub fn get_config_flag(field_name: &str) -> bool {
+    get_config_flag_or(field_name, false)
+}
+
+/// Check whether the given project-local git-req boolean config is enabled, if it's set
+pub fn get_config_flag_or(field_name: &str, default: bool) -> bool {
+    let repo = match Repository::open_from_env() {
+        Ok(repo) => repo,
+        Err(_) => return default,
+    };
+    let cfg = repo.config().unwrap();
+    cfg.get_bool(&format!("req.{}", field_name)).unwrap_or(default)
+}
+
 /// Set a value for the project-local git-req configuration
 pub fn set_config(field_name: &str, value: &str) {
     let repo = Repository::open_from_env().expect("Couldn't find repository");
@@ -82,20 +138,311 @@ pub fn delete_req_config(domain: &str, field: &str) -> Result<(), Error> {
     cfg.remove(&format!("req.{}.{}", slug, field))
 }
 
-/// Check out a branch by name
-pub fn checkout_branch(remote_branch_name: &str, local_branch_name: &str) -> Result<bool, String> {
+/// Fetch a remote ref into a local branch. If `force` is set, an existing local branch is
+/// updated to match the remote even if that isn't a fast-forward. If `partial` is set, blobs are
+/// left out of the fetch and retrieved lazily.
+pub fn fetch_ref(
+    remote_name: &str,
+    remote_ref: &str,
+    local_branch_name: &str,
+    force: bool,
+    partial: bool,
+) -> Result<(), String> {
+    let refspec = format!(
+        "{}{}:{}",
+        if force { "+" } else { "" },
+        remote_ref,
+        local_branch_name
+    );
+    debug!("Fetching {}", refspec);
+    let mut args = vec!["fetch"];
+    if partial {
+        args.push("--filter=blob:none");
+    }
+    args.push(remote_name);
+    args.push(&refspec);
+    // Keep stdout clean for callers that print to it
+    match cmd("git", &args).stdout_to_stderr().run() {
+        Ok(_) => Ok(()),
+        Err(err) => Err(format!("Could not fetch {}: {}", remote_ref, err)),
+    }
+}
+
+/// Fetch a remote ref's objects without updating any local branch
+pub fn fetch_objects(remote_name: &str, remote_ref: &str) -> Result<(), String> {
+    debug!("Fetching objects for {}", remote_ref);
+    match cmd!("git", "fetch", remote_name, remote_ref).stdout_to_stderr().run() {
+        Ok(_) => Ok(()),
+        Err(err) => Err(format!("Could not fetch {}: {}", remote_ref, err)),
+    }
+}
+
+/// Resolve a revision to the full ID of the commit it points at, if it exists locally
+pub fn resolve_commit(rev: &str) -> Option<String> {
+    let repo = Repository::open_from_env().ok()?;
+    let object = repo.revparse_single(rev).ok()?;
+    let commit = object.peel_to_commit().ok()?;
+    Some(commit.id().to_string())
+}
+
+/// Count the commits reachable from `to` that aren't reachable from `from`
+pub fn count_commits(from: &str, to: &str) -> Result<usize, String> {
+    let repo = Repository::open_from_env().map_err(|err| err.to_string())?;
+    let mut revwalk = repo.revwalk().map_err(|err| err.to_string())?;
+    revwalk
+        .push_range(&format!("{}..{}", from, to))
+        .map_err(|err| format!("Could not compare {} and {}: {}", from, to, err))?;
+    Ok(revwalk.count())
+}
+
+/// Add a line to a branch's description (`branch.<name>.description`), unless it's already there
+pub fn add_branch_description_line(branch_name: &str, line: &str) -> Result<(), String> {
+    let key = format!("branch.{}.description", branch_name);
+    let description = match get_repo_info(&key) {
+        Ok(description) => {
+            if description.lines().any(|existing| existing == line) {
+                return Ok(());
+            }
+            format!("{}\n{}", description.trim_end(), line)
+        }
+        Err(_) => String::from(line),
+    };
     let repo = Repository::open_from_env().expect("Couldn't find repository");
-    // Fetch the remote branch if there's no local branch with the correct name
-    if repo.revparse_single(local_branch_name).is_err() {
-        cmd!(
-            "git",
-            "fetch",
-            "origin",
-            &format!("{}:{}", remote_branch_name, local_branch_name)
-        )
+    let mut cfg = repo.config().unwrap();
+    cfg.set_str(&key, &description).map_err(|err| err.to_string())
+}
+
+/// Remove the lines of a branch's description (`branch.<name>.description`) that `matches`
+/// picks out, removing the description if nothing's left
+pub fn remove_branch_description_lines<F>(branch_name: &str, matches: F) -> Result<(), String>
+where
+    F: Fn(&str) -> bool,
+{
+    let key = format!("branch.{}.description", branch_name);
+    let description = match get_repo_info(&key) {
+        Ok(description) => description,
+        Err(_) => return Ok(()),
+    };
+    let kept: Vec<&str> = description.lines().filter(|line| !matches(line)).collect();
+    let repo = Repository::open_from_env().map_err(|err| err.to_string())?;
+    let mut cfg = repo.config().map_err(|err| err.to_string())?;
+    if kept.iter().all(|line| line.trim().is_empty()) {
+        cfg.remove(&key).map_err(|err| err.to_string())
+    } else {
+        cfg.set_str(&key, &kept.join("\n")).map_err(|err| err.to_string())
+    }
+}
+
+/// Record the request a branch was checked out for (`branch.<name>.reqid`)
+pub fn set_branch_request_id(branch_name: &str, mr_id: i64) -> Result<(), String> {
+    let repo = Repository::open_from_env().map_err(|err| err.to_string())?;
+    let mut cfg = repo.config().map_err(|err| err.to_string())?;
+    cfg.set_i64(&format!("branch.{}.reqid", branch_name), mr_id)
+        .map_err(|err| err.to_string())
+}
+
+/// Check whether a name can be given to a local branch
+pub fn is_valid_branch_name(name: &str) -> bool {
+    // Git refuses these as branch names, though they're valid refs
+    !name.starts_with('-')
+        && name != "HEAD"
+        && Reference::is_valid_name(&format!("refs/heads/{}", name))
+}
+
+/// Check whether the repository has a local branch with the given name
+pub fn branch_exists(branch_name: &str) -> bool {
+    Repository::open_from_env()
+        .map(|repo| repo.find_branch(branch_name, BranchType::Local).is_ok())
+        .unwrap_or(false)
+}
+
+/// Get the request a branch was checked out for, if any
+pub fn branch_request_id(branch_name: &str) -> Option<i64> {
+    let repo = Repository::open_from_env().ok()?;
+    let key = format!("branch.{}.reqid", branch_name);
+    repo.config().ok()?.get_i64(&key).ok()
+}
+
+/// Get the name of the branch HEAD is on, if it's on one
+pub fn current_branch() -> Option<String> {
+    let repo = Repository::open_from_env().ok()?;
+    let head = repo.head().ok()?;
+    if !head.is_branch() {
+        return None;
+    }
+    head.shorthand().map(String::from)
+}
+
+/// Get the request the current branch was checked out for, if any. This only reads HEAD and
+/// the config, so it's cheap enough to run from a shell prompt.
+pub fn current_branch_request_id() -> Option<i64> {
+    let repo = Repository::open_from_env().ok()?;
+    let head = repo.head().ok()?;
+    if !head.is_branch() {
+        return None;
+    }
+    let key = format!("branch.{}.reqid", head.shorthand()?);
+    repo.config().ok()?.get_i64(&key).ok()
+}
+
+/// Get the commit HEAD is detached at, if it isn't on a branch
+pub fn detached_head() -> Option<String> {
+    let repo = Repository::open_from_env().ok()?;
+    if !repo.head_detached().unwrap_or(false) {
+        return None;
+    }
+    let head = repo.head().ok()?.peel_to_commit().ok()?;
+    Some(head.id().to_string())
+}
+
+/// What to do with the branch a request is checked out into with `--as`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ChosenBranch {
+    /// There's no branch with the name yet, so the checkout creates it
+    Create,
+    /// The branch was checked out for this request before, so it's checked out as it is
+    Reuse,
+    /// The branch is reset to the request's head first
+    Reset,
+}
+
+impl ChosenBranch {
+    /// Decide what to do with the branch `name`, given whether it exists, the request it was
+    /// checked out for and whether it's checked out now. An existing branch is only reused if
+    /// it was checked out for this request; otherwise it takes `force`, which resets it.
+    pub fn decide(
+        name: &str,
+        exists: bool,
+        request_id: Option<i64>,
+        is_current: bool,
+        mr_id: i64,
+        force: bool,
+    ) -> Result<ChosenBranch, String> {
+        match (exists, force) {
+            (false, _) => Ok(ChosenBranch::Create),
+            (true, false) if request_id == Some(mr_id) => Ok(ChosenBranch::Reuse),
+            (true, false) => Err(format!(
+                "There's already a branch named {}; pass --force to reset it to the request",
+                name
+            )),
+            (true, true) if is_current => Err(format!(
+                "{} is checked out, so it can't be reset; switch to another branch first",
+                name
+            )),
+            (true, true) => Ok(ChosenBranch::Reset),
+        }
+    }
+}
+
+/// What to do when a request is checked out while HEAD is detached (`req.detachedpolicy`)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DetachedPolicy {
+    /// Check the request's branch out as usual
+    Branch,
+    /// Warn that the detached commit is being left behind, then check the branch out
+    Warn,
+    /// Don't check anything out
+    Refuse,
+}
+
+impl DetachedPolicy {
+    /// Parse the `req.detachedpolicy` setting, defaulting to checking the branch out as usual
+    pub fn from_config(value: Option<String>) -> Result<DetachedPolicy, String> {
+        match value.as_ref().map(|value| value.trim().to_lowercase()) {
+            None => Ok(DetachedPolicy::Branch),
+            Some(ref value) if value == "branch" => Ok(DetachedPolicy::Branch),
+            Some(ref value) if value == "warn" => Ok(DetachedPolicy::Warn),
+            Some(ref value) if value == "refuse" => Ok(DetachedPolicy::Refuse),
+            Some(value) => Err(format!(
+                "Unknown detached HEAD policy {:?}; expected branch, warn or refuse",
+                value
+            )),
+        }
+    }
+
+    /// Decide whether a checkout can go ahead with HEAD detached at `detached` (if it is),
+    /// returning the warning to show first, if any
+    pub fn check(self, detached: Option<&str>) -> Result<Option<String>, String> {
+        let commit = match detached {
+            Some(commit) => &commit[..commit.len().min(10)],
+            None => return Ok(None),
+        };
+        match self {
+            DetachedPolicy::Branch => Ok(None),
+            DetachedPolicy::Warn => Ok(Some(format!(
+                "Warning: HEAD is detached at {}. Once you switch away, commits made there \
+                 are only reachable from the reflog; create a branch to keep them",
+                commit
+            ))),
+            DetachedPolicy::Refuse => Err(format!(
+                "HEAD is detached at {}, and req.detachedpolicy is refuse. Create a branch for \
+                 any work there, or check out a branch, then try again",
+                commit
+            )),
+        }
+    }
+}
+
+/// Check whether the working tree's `.gitattributes` stores any files with Git LFS
+pub fn uses_lfs() -> bool {
+    workdir()
+        .and_then(|workdir| fs::read_to_string(workdir.join(".gitattributes")).ok())
+        .is_some_and(|attributes| attributes_use_lfs(&attributes))
+}
+
+/// Check whether `.gitattributes` contents route any paths through the LFS filter
+fn attributes_use_lfs(attributes: &str) -> bool {
+    attributes
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.starts_with('#'))
+        .any(|line| {
+            line.split_whitespace()
+                .skip(1)
+                .any(|attr| attr == "filter=lfs")
+        })
+}
+
+/// Check whether the `git lfs` command is installed
+pub fn lfs_available() -> bool {
+    cmd!("git", "lfs", "version")
+        .stdout_null()
+        .stderr_null()
         .run()
-        .unwrap();
-        if repo.revparse_single(&local_branch_name).is_err() {
+        .is_ok()
+}
+
+/// Fetch the LFS objects for a local branch from the remote and replace the pointer files in
+/// the working tree with their content
+pub fn lfs_fetch_and_checkout(remote_name: &str, branch_name: &str) -> Result<(), String> {
+    debug!("Fetching LFS objects for {}", branch_name);
+    cmd!("git", "lfs", "fetch", remote_name, branch_name)
+        .stdout_to_stderr()
+        .run()
+        .map_err(|err| format!("Could not fetch LFS objects: {}", err))?;
+    cmd!("git", "lfs", "checkout")
+        .stdout_to_stderr()
+        .run()
+        .map_err(|err| format!("Could not check out LFS files: {}", err))?;
+    Ok(())
+}
+
+/// Check out a branch by name, passing `hook_env` to any hooks the checkout runs. A branch that
+/// has to be fetched first is fetched without file contents if `partial_fetch` is set.
+pub fn checkout_branch(
+    remote_name: &str,
+    remote_branch_name: &str,
+    local_branch_name: &str,
+    hook_env: &[(&str, String)],
+    partial_fetch: bool,
+) -> Result<bool, String> {
+    let repo = Repository::open_from_env().expect("Couldn't find repository");
+    // Fetch the remote branch if there's no local branch with the correct name. Only branches
+    // count, so a tag or commit that happens to have the name isn't checked out instead.
+    let local_ref = format!("refs/heads/{}", local_branch_name);
+    if repo.find_reference(&local_ref).is_err() {
+        fetch_ref(remote_name, remote_branch_name, local_branch_name, false, partial_fetch)?;
+        if repo.find_reference(&local_ref).is_err() {
             return Err(format!(
                 "Could not find remote branch: {}",
                 local_branch_name
@@ -103,8 +450,126 @@ pub fn checkout_branch(remote_branch_name: &str, local_branch_name: &str) -> Res
         }
     }
     debug!("Checking out branch!");
-    match cmd!("git", "checkout", local_branch_name).run() {
+    let checkout = hook_env
+        .iter()
+        .fold(cmd!("git", "checkout", local_branch_name), |expr, (key, value)| {
+            expr.env(key, value)
+        });
+    match checkout.run() {
         Ok(_) => Ok(true),
         Err(err) => Err(format!("Could not check out local branch: {}", err))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COMMIT: &str = "3f1e1d9a6c2b7e8f0a4d5c6b7a8e9f0d1c2b3a4e";
+
+    #[test]
+    fn test_is_valid_branch_name() {
+        assert!(is_valid_branch_name("review/alice-login"));
+        assert!(is_valid_branch_name("fix-42"));
+        assert!(!is_valid_branch_name("two words"));
+        assert!(!is_valid_branch_name("fix..things"));
+        assert!(!is_valid_branch_name("fix~1"));
+        assert!(!is_valid_branch_name("ends-with.lock"));
+        assert!(!is_valid_branch_name("-f"));
+        assert!(!is_valid_branch_name("HEAD"));
+        assert!(!is_valid_branch_name(""));
+    }
+
+    #[test]
+    fn test_attributes_use_lfs() {
+        assert!(attributes_use_lfs(
+            "*.psd filter=lfs diff=lfs merge=lfs -text\n"
+        ));
+        assert!(attributes_use_lfs(
+            "*.rs text\n\n  assets/** filter=lfs -text\n"
+        ));
+        assert!(!attributes_use_lfs(
+            "# *.psd filter=lfs diff=lfs\n*.rs text eol=lf\n"
+        ));
+        assert!(!attributes_use_lfs("filter=lfs\n"));
+        assert!(!attributes_use_lfs(""));
+    }
+
+    #[test]
+    fn test_chosen_branch_new() {
+        let decision = ChosenBranch::decide("review", false, None, false, 42, false);
+        assert_eq!(Ok(ChosenBranch::Create), decision);
+        let forced = ChosenBranch::decide("review", false, None, false, 42, true);
+        assert_eq!(Ok(ChosenBranch::Create), forced);
+    }
+
+    #[test]
+    fn test_chosen_branch_reuse() {
+        let decision = ChosenBranch::decide("review", true, Some(42), true, 42, false);
+        assert_eq!(Ok(ChosenBranch::Reuse), decision);
+    }
+
+    #[test]
+    fn test_chosen_branch_collision() {
+        let other_request = ChosenBranch::decide("review", true, Some(7), false, 42, false);
+        assert_eq!(
+            Err(String::from(
+                "There's already a branch named review; pass --force to reset it to the request"
+            )),
+            other_request
+        );
+        // A branch git-req didn't check out isn't taken over either
+        assert!(ChosenBranch::decide("main", true, None, false, 42, false).is_err());
+    }
+
+    #[test]
+    fn test_chosen_branch_force() {
+        let decision = ChosenBranch::decide("review", true, Some(7), false, 42, true);
+        assert_eq!(Ok(ChosenBranch::Reset), decision);
+        let checked_out = ChosenBranch::decide("review", true, Some(7), true, 42, true);
+        assert_eq!(
+            Err(String::from(
+                "review is checked out, so it can't be reset; switch to another branch first"
+            )),
+            checked_out
+        );
+    }
+
+    #[test]
+    fn test_detached_policy_from_config() {
+        assert_eq!(Ok(DetachedPolicy::Branch), DetachedPolicy::from_config(None));
+        assert_eq!(
+            Ok(DetachedPolicy::Warn),
+            DetachedPolicy::from_config(Some(String::from(" Warn")))
+        );
+        assert_eq!(
+            Ok(DetachedPolicy::Refuse),
+            DetachedPolicy::from_config(Some(String::from("refuse")))
+        );
+        assert!(DetachedPolicy::from_config(Some(String::from("detach"))).is_err());
+    }
+
+    #[test]
+    fn test_detached_policy_on_a_branch() {
+        for policy in &[DetachedPolicy::Branch, DetachedPolicy::Warn, DetachedPolicy::Refuse] {
+            assert_eq!(Ok(None), policy.check(None));
+        }
+    }
+
+    #[test]
+    fn test_detached_policy_branch() {
+        assert_eq!(Ok(None), DetachedPolicy::Branch.check(Some(COMMIT)));
+    }
+
+    #[test]
+    fn test_detached_policy_warn() {
+        let warning = DetachedPolicy::Warn.check(Some(COMMIT)).unwrap().unwrap();
+        assert!(warning.contains("detached at 3f1e1d9a6c."));
+    }
+
+    #[test]
+    fn test_detached_policy_refuse() {
+        let error = DetachedPolicy::Refuse.check(Some(COMMIT)).unwrap_err();
+        assert!(error.starts_with("HEAD is detached at 3f1e1d9a6c,"));
+    }
+}
@@ -1,73 +1,792 @@
 ///! GIT REQ!
+mod audit;
+mod batch;
+mod browser;
+mod ci;
+mod difftool;
 mod git;
 mod remotes;
+mod stack;
+#[cfg(test)]
+mod testing;
+mod watch;
 
 use clap::{crate_authors, crate_version, App, Arg, ArgGroup};
 use git2::ErrorCode;
 use log::{debug, error, info, trace};
-use std::io::{self, Write};
+use std::io::{self, BufRead, Read, Write};
 use std::{env, process};
 use tabwriter::TabWriter;
 
-/// Get the `origin` remote
-fn get_origin() -> String {
-    git::get_remote_url("origin")
+/// Get the URL of the remote being queried
+fn get_origin(remote_name: &str) -> String {
+    git::get_remote_url(remote_name)
 }
 
-/// Get the remote for the current project
-fn get_remote(fetch_api_key: bool) -> Result<Box<dyn remotes::Remote>, String> {
-    let origin = get_origin();
-    remotes::get_remote(&origin, !fetch_api_key)
+/// Get the URL of the remote being queried for `purpose`, which may be its push URL depending
+/// on `req.pushurl`
+fn get_origin_for(remote_name: &str, purpose: remotes::UrlPurpose) -> Result<String, String> {
+    let fetch_url = get_origin(remote_name);
+    let push_url = git::find_remote_push_url(remote_name);
+    let policy = remotes::PushUrlPolicy::from_config(git::get_config("pushurl"))?;
+    let origin = policy.select(purpose, &fetch_url, push_url.as_deref());
+    if origin != fetch_url {
+        debug!("Using the push URL of {} for {:?}", remote_name, purpose);
+    }
+    Ok(String::from(origin))
 }
 
-/// Get the remote, fail hard otherwise
-fn get_remote_hard(fetch_api_key: bool) -> Box<dyn remotes::Remote> {
-    match get_remote(fetch_api_key) {
-        Ok(x) => x,
-        Err(error) => {
-            eprintln!(
-                "There was a problem finding the remote Git repo: {}",
-                &error
-            );
-            process::exit(1);
+/// Pick the git remote to query for requests. An explicit `--remote` wins, then the remote
+/// chosen for this repository before. Otherwise `origin` is used, unless the remotes point at
+/// different projects, in which case the user is asked to choose one.
+fn resolve_remote_name(explicit: Option<&str>) -> Result<String, String> {
+    if let Some(name) = explicit {
+        return match git::find_remote_url(name) {
+            Some(_) => Ok(String::from(name)),
+            None => Err(format!("There's no remote named {}", name)),
+        };
+    }
+    let remotes = git::list_remotes();
+    if let Some(name) = git::get_config("remote") {
+        if remotes.iter().any(|(remote, _)| *remote == name) {
+            return Ok(name);
         }
     }
+    if remotes.is_empty() {
+        return Ok(String::from("origin"));
+    }
+    if let Some(name) = remotes::default_remote(&remotes) {
+        return Ok(String::from(name));
+    }
+    let names: Vec<&str> = remotes.iter().map(|(name, _)| name.as_str()).collect();
+    if !atty::is(atty::Stream::Stdin) {
+        return Err(format!(
+            "The remotes {} point at different projects; pass --remote to pick one",
+            names.join(", ")
+        ));
+    }
+    let name = choose_remote(&remotes)?;
+    git::set_config("remote", &name);
+    eprintln!(
+        "Using {} from now on; change it with `git config req.remote <name>`",
+        name
+    );
+    Ok(name)
+}
+
+/// Ask the user which of the remotes to query
+fn choose_remote(remotes: &[(String, String)]) -> Result<String, String> {
+    eprintln!("The remotes for this repository point at different projects:");
+    let mut tw = TabWriter::new(io::stderr()).padding(2);
+    for (index, (name, url)) in remotes.iter().enumerate() {
+        let project = remotes::describe_project(url).unwrap_or_else(|| remotes::redact_origin(url));
+        writeln!(&mut tw, "  {})\t{}\t{}", index + 1, name, project).unwrap();
+    }
+    tw.flush().unwrap();
+    eprint!("Which one should git-req use? ");
+    let _ = io::stderr().flush();
+    let mut answer = String::new();
+    io::stdin()
+        .read_line(&mut answer)
+        .map_err(|error| format!("There was a problem reading the answer: {}", error))?;
+    let answer = answer.trim();
+    let chosen = match answer.parse::<usize>() {
+        Ok(index) if index >= 1 => remotes.get(index - 1),
+        _ => remotes.iter().find(|(name, _)| name == answer),
+    };
+    chosen
+        .map(|(name, _)| name.clone())
+        .ok_or_else(|| format!("{:?} isn't one of the remotes", answer))
+}
+
+/// Get the remote for the current project
+fn get_remote(remote_name: &str, fetch_api_key: bool) -> Result<Box<dyn remotes::Remote>, String> {
+    let origin = get_origin_for(remote_name, remotes::UrlPurpose::Read)?;
+    remotes::get_remote(&origin, !fetch_api_key)
+}
+
+/// Get the remote to change requests on or link to them with, describing the failure otherwise
+fn get_write_remote_or_describe(remote_name: &str) -> Result<Box<dyn remotes::Remote>, String> {
+    get_origin_for(remote_name, remotes::UrlPurpose::Write)
+        .and_then(|origin| remotes::get_remote(&origin, false))
+        .map_err(|error| format!("There was a problem finding the remote Git repo: {}", error))
+}
+
+/// Get the remote, describing the failure otherwise
+fn get_remote_or_describe(
+    remote_name: &str,
+    fetch_api_key: bool,
+) -> Result<Box<dyn remotes::Remote>, String> {
+    get_remote(remote_name, fetch_api_key)
+        .map_err(|error| format!("There was a problem finding the remote Git repo: {}", error))
+}
+
+/// What checking a request out found on the way, for commands that go on to use it
+struct CheckedOut {
+    remote: Box<dyn remotes::Remote>,
+    /// The request's details, unless they couldn't be fetched
+    mr: Option<remotes::MergeRequest>,
+    /// The local branch the request was checked out into
+    branch_name: String,
 }
 
 /// Check out the branch corresponding to the MR ID
-fn checkout_mr(mr_id: i64) {
-    info!("Getting MR: {}", mr_id);
-    let mut remote = get_remote_hard(true);
+fn checkout_mr(
+    remote_name: &str,
+    fetch_remote: &str,
+    mr_id: i64,
+    branch_name: Option<&str>,
+    force: bool,
+    partial_fetch: bool,
+) -> Result<(), String> {
+    checkout_request(remote_name, fetch_remote, mr_id, branch_name, force, partial_fetch)
+        .map(|_| ())
+}
+
+/// Check out the branch corresponding to the MR ID, returning what was fetched to do so
+fn checkout_request(
+    remote_name: &str,
+    fetch_remote: &str,
+    mr_id: i64,
+    branch_name: Option<&str>,
+    force: bool,
+    partial_fetch: bool,
+) -> Result<CheckedOut, String> {
+    if let Some(name) = branch_name.filter(|name| !git::is_valid_branch_name(name)) {
+        return Err(format!("{:?} isn't a valid branch name", name));
+    }
+    check_detached_head()?;
+    let mut remote = get_remote_or_describe(remote_name, true)?;
     debug!("Found remote: {}", remote);
-    let remote_branch_name = match remote.get_remote_req_branch(mr_id) {
-        Ok(name) => name,
-        Err(error) => {
-            eprintln!(
-                "There was a problem ascertaining the branch name: {}",
-                &error
-            );
-            process::exit(1);
+    let term = remote.terminology();
+    info!("Getting {}: {}", term.abbreviation, mr_id);
+    // The request's details are only needed for hooks and trailers, so don't fail without them.
+    // When they're there, the branches are taken from them rather than looked up again.
+    let mr = remote
+        .get_req(mr_id)
+        .map_err(|error| debug!("Couldn't get {} details: {}", term.abbreviation, error))
+        .ok();
+    let remote_branch_name = match mr {
+        Some(ref mr) => remote.get_remote_req_branch_of(mr),
+        None => remote.get_remote_req_branch(mr_id),
+    }
+    .map_err(|error| {
+        format!(
+            "There was a problem ascertaining the branch name for {} {}: {}",
+            term.noun, mr_id, error
+        )
+    })?;
+    debug!("Got remote branch name: {}", remote_branch_name);
+    let trailer = git::get_config("trailer");
+    let mut hook_env = mr.as_ref().map(|mr| mr.hook_env()).unwrap_or_default();
+    if let (Some(mr), Some(template)) = (&mr, &trailer) {
+        hook_env.push(("GIT_REQ_TRAILER", mr.render_trailer(template)));
+    }
+    let local_branch_name = match branch_name {
+        Some(name) => {
+            prepare_chosen_branch(
+                fetch_remote,
+                &remote_branch_name,
+                name,
+                mr_id,
+                force,
+                partial_fetch,
+            )?;
+            String::from(name)
         }
+        None => match mr {
+            Some(ref mr) => remote.get_local_req_branch_of(mr).unwrap(),
+            None => remote.get_local_req_branch(mr_id).unwrap(),
+        },
     };
-    debug!("Got remote branch name: {}", remote_branch_name);
-    match git::checkout_branch(
+    git::checkout_branch(
+        fetch_remote,
         &remote_branch_name,
-        &remote.get_local_req_branch(mr_id).unwrap(),
-    ) {
-        Ok(_) => {
-            info!("Done!");
+        &local_branch_name,
+        &hook_env,
+        partial_fetch,
+    )
+    .map_err(|error| format!("There was an error checking out the branch: {}", error))?;
+    record_branch_request_id(&local_branch_name, mr_id);
+    fetch_lfs_content(fetch_remote, &local_branch_name);
+    if let (Some(mr), Some(template)) = (&mr, &trailer) {
+        git::add_branch_description_line(&local_branch_name, &mr.render_trailer(template))
+            .map_err(|error| format!("There was an error recording the trailer: {}", error))?;
+    }
+    info!("Done!");
+    Ok(CheckedOut {
+        remote,
+        mr,
+        branch_name: local_branch_name,
+    })
+}
+
+/// Get a branch chosen with `--as` ready to check the request out into. An existing branch is
+/// only reused if it was checked out for this request before; otherwise it takes `--force`,
+/// which resets the branch to the request's head.
+fn prepare_chosen_branch(
+    fetch_remote: &str,
+    remote_branch_name: &str,
+    branch_name: &str,
+    mr_id: i64,
+    force: bool,
+    partial_fetch: bool,
+) -> Result<(), String> {
+    let decision = git::ChosenBranch::decide(
+        branch_name,
+        git::branch_exists(branch_name),
+        git::branch_request_id(branch_name),
+        git::current_branch().as_deref() == Some(branch_name),
+        mr_id,
+        force,
+    )?;
+    if decision != git::ChosenBranch::Reset {
+        return Ok(());
+    }
+    git::fetch_ref(fetch_remote, remote_branch_name, branch_name, true, partial_fetch)
+        .map_err(|error| format!("There was an error resetting {}: {}", branch_name, error))?;
+    // The branch is for another request now, so the trailer recorded for the old one goes
+    if let Some(template) = git::get_config("trailer") {
+        git::remove_branch_description_lines(branch_name, |line| {
+            remotes::MergeRequest::is_rendered_trailer(&template, line)
+        })
+        .map_err(|error| format!("There was an error clearing the old trailer: {}", error))?;
+    }
+    Ok(())
+}
+
+/// Replace LFS pointer files in a checked-out branch with their content, unless `req.lfs` is
+/// off. Failing to isn't fatal, since the branch itself is checked out.
+fn fetch_lfs_content(fetch_remote: &str, branch_name: &str) {
+    if !git::get_config_flag_or("lfs", true) || !git::uses_lfs() {
+        return;
+    }
+    if !git::lfs_available() {
+        eprintln!(
+            "Warning: this repository stores files with Git LFS, but git-lfs isn't installed, \
+             so they're checked out as pointer files"
+        );
+        return;
+    }
+    info!("Fetching LFS content for {}", branch_name);
+    if let Err(error) = git::lfs_fetch_and_checkout(fetch_remote, branch_name) {
+        eprintln!("Warning: {}", error);
+    }
+}
+
+/// Check out the branch corresponding to the MR ID and show its changes against the target
+fn review_mr(
+    remote_name: &str,
+    fetch_remote: &str,
+    mr_id: i64,
+    branch_name: Option<&str>,
+    force: bool,
+    partial_fetch: bool,
+) -> Result<(), String> {
+    let mut checked_out =
+        checkout_request(remote_name, fetch_remote, mr_id, branch_name, force, partial_fetch)?;
+    let term = checked_out.remote.terminology();
+    let target_branch = match checked_out.mr {
+        Some(mr) => mr.target_branch,
+        None => {
+            checked_out
+                .remote
+                .get_req(mr_id)
+                .map_err(|error| {
+                    format!("There was a problem getting {} {}: {}", term.noun, mr_id, error)
+                })?
+                .target_branch
+        }
+    };
+    // Diff against the target as the remote has it now, not a stale local copy
+    info!("Fetching the target branch {}", target_branch);
+    git::fetch_objects(fetch_remote, &target_branch)?;
+    let base = format!("{}/{}", fetch_remote, target_branch);
+    let base = match git::resolve_commit(&base) {
+        Some(_) => base,
+        None => String::from("FETCH_HEAD"),
+    };
+    let head = checked_out.branch_name;
+    let difftool = git::get_config("difftool");
+    difftool::diff_command(difftool.as_deref(), &base, &head)
+        .run()
+        .map_err(|error| format!("There was a problem showing the diff: {}", error))?;
+    Ok(())
+}
+
+/// Read `req.partialfetch`, warning once that file contents will be fetched on demand if it's set
+fn partial_fetch_setting() -> bool {
+    let partial_fetch = git::get_config_flag("partialfetch");
+    if partial_fetch {
+        eprintln!(
+            "Warning: req.partialfetch is set, so request branches are fetched without file \
+             contents; checkouts, diffs and other operations that need them will fetch them on \
+             demand"
+        );
+    }
+    partial_fetch
+}
+
+/// Apply `req.detachedpolicy` before switching away from a detached HEAD
+fn check_detached_head() -> Result<(), String> {
+    let policy = git::DetachedPolicy::from_config(git::get_config("detachedpolicy"))?;
+    if let Some(warning) = policy.check(git::detached_head().as_deref())? {
+        eprintln!("{}", warning);
+    }
+    Ok(())
+}
+
+/// Fetch the branch corresponding to the MR ID without checking it out, and print its name. An
+/// existing branch is only fast-forwarded, unless `force` is set to reset it to the request.
+fn pick_mr(
+    remote_name: &str,
+    fetch_remote: &str,
+    mr_id: i64,
+    force: bool,
+    partial_fetch: bool,
+) -> Result<(), String> {
+    let mut remote = get_remote_or_describe(remote_name, true)?;
+    debug!("Found remote: {}", remote);
+    let term = remote.terminology();
+    let remote_branch_name = remote.get_remote_req_branch(mr_id).map_err(|error| {
+        format!(
+            "There was a problem ascertaining the branch name for {} {}: {}",
+            term.noun, mr_id, error
+        )
+    })?;
+    let local_branch_name = remote.get_local_req_branch(mr_id).unwrap();
+    // git won't update the checked-out branch, since that would leave the working tree behind
+    if git::current_branch().as_deref() == Some(local_branch_name.as_str()) {
+        return Err(format!(
+            "{} is checked out, so it can't be picked; switch to another branch first",
+            local_branch_name
+        ));
+    }
+    let existed = git::branch_exists(&local_branch_name);
+    git::fetch_ref(fetch_remote, &remote_branch_name, &local_branch_name, force, partial_fetch)
+        .map_err(|error| {
+            if existed && !force {
+                format!(
+                    "There was an error updating {}: {}\nIf it has commits the {} doesn't, \
+                     pass --force to reset it to the {}, discarding them",
+                    local_branch_name, error, term.abbreviation, term.abbreviation
+                )
+            } else {
+                format!("There was an error fetching the branch: {}", error)
+            }
+        })?;
+    record_branch_request_id(&local_branch_name, mr_id);
+    println!("{}", local_branch_name);
+    Ok(())
+}
+
+/// Remember which request a branch is for, so `--prompt` can show it
+fn record_branch_request_id(branch_name: &str, mr_id: i64) {
+    if let Err(error) = git::set_branch_request_id(branch_name, mr_id) {
+        eprintln!("Warning: couldn't record the request for {}: {}", branch_name, error);
+    }
+}
+
+/// Print a shell prompt segment for the request the current branch is for, if any
+fn print_prompt_segment() {
+    if let Some(mr_id) = git::current_branch_request_id() {
+        println!("req:{}", mr_id);
+    }
+}
+
+/// Report whether the local branch for the MR with the given ID matches the remote head
+fn check_mr(remote_name: &str, fetch_remote: &str, mr_id: i64) -> Result<(), String> {
+    let mut remote = get_remote_or_describe(remote_name, true)?;
+    debug!("Found remote: {}", remote);
+    let term = remote.terminology();
+    let local_branch_name = remote.get_local_req_branch(mr_id).unwrap();
+    let local_tip = git::resolve_commit(&local_branch_name).ok_or_else(|| {
+        format!(
+            "There's no local branch {} for {} {}; run `git req {}` to check it out",
+            local_branch_name, term.noun, mr_id, mr_id
+        )
+    })?;
+    let head_sha = remote
+        .get_req(mr_id)
+        .map_err(|error| format!("There was a problem getting {} {}: {}", term.noun, mr_id, error))?
+        .head_sha
+        .ok_or_else(|| {
+            format!(
+                "The provider didn't report a head commit for {} {}",
+                term.noun, mr_id
+            )
+        })?;
+    // Only fetch if the head commit isn't already known locally
+    let head = match git::resolve_commit(&head_sha) {
+        Some(head) => head,
+        None => {
+            let remote_branch_name = remote.get_remote_req_branch(mr_id).map_err(|error| {
+                format!(
+                    "There was a problem ascertaining the branch name for {} {}: {}",
+                    term.noun, mr_id, error
+                )
+            })?;
+            git::fetch_objects(fetch_remote, &remote_branch_name)?;
+            git::resolve_commit(&head_sha).ok_or_else(|| {
+                format!("Could not find the head commit {} after fetching", head_sha)
+            })?
+        }
+    };
+    if head == local_tip {
+        println!("{} is up to date", local_branch_name);
+        return Ok(());
+    }
+    let behind = git::count_commits(&local_tip, &head)?;
+    let ahead = git::count_commits(&head, &local_tip)?;
+    match (behind, ahead) {
+        (behind, 0) => println!("{} is {} commits behind", local_branch_name, behind),
+        (0, ahead) => println!("{} is {} commits ahead", local_branch_name, ahead),
+        (behind, ahead) => println!(
+            "{} is {} commits behind and {} ahead (the {} was rewritten)",
+            local_branch_name, behind, ahead, term.abbreviation
+        ),
+    }
+    Ok(())
+}
+
+/// Check that the MR with the given ID meets each of the requirements, failing with the ones
+/// it doesn't
+fn check_requirements(
+    remote_name: &str,
+    mr_id: i64,
+    requirements: &[remotes::Requirement],
+) -> Result<(), String> {
+    let mut remote = get_remote_or_describe(remote_name, true)?;
+    debug!("Found remote: {}", remote);
+    let term = remote.terminology();
+    let status = remote.get_req_status(mr_id).map_err(|error| {
+        format!("There was a problem getting the status of {} {}: {}", term.noun, mr_id, error)
+    })?;
+    debug!("Status: {:?}", status);
+    let failures: Vec<String> = requirements
+        .iter()
+        .filter_map(|requirement| requirement.check(&status).err())
+        .collect();
+    if !failures.is_empty() {
+        return Err(format!(
+            "{} {} doesn't meet the requirements: {}",
+            term.abbreviation,
+            mr_id,
+            failures.join("; ")
+        ));
+    }
+    println!("{} {} meets the requirements", term.abbreviation, mr_id);
+    Ok(())
+}
+
+/// Poll the merge gates of the MR with the given ID until Ctrl-C is pressed, notifying when it
+/// becomes mergeable or its pipeline finishes
+fn watch_mr(remote_name: &str, mr_id: i64, interval: Option<&str>) -> Result<(), String> {
+    let secs = watch::interval(interval)?;
+    let requested = interval.and_then(|value| value.trim().parse::<u64>().ok());
+    if requested.is_some_and(|requested| requested < secs) {
+        eprintln!("Polling every {} seconds, the shortest interval allowed", secs);
+    }
+    let mut remote = get_remote_or_describe(remote_name, true)?;
+    debug!("Found remote: {}", remote);
+    let term = remote.terminology();
+    let desktop = browser::has_display(|name| env::var(name).ok());
+    let redraw = atty::is(atty::Stream::Stdout);
+    watch::catch_interrupts();
+    let mut previous: Option<remotes::RequestStatus> = None;
+    loop {
+        match remote.get_req_status(mr_id) {
+            Ok(status) => {
+                let line = format!(
+                    "{} {}: {}",
+                    term.abbreviation,
+                    mr_id,
+                    watch::status_line(&status)
+                );
+                if redraw {
+                    print!("\r\x1b[2K{}", line);
+                    let _ = io::stdout().flush();
+                } else if previous.as_ref() != Some(&status) {
+                    println!("{}", line);
+                }
+                if let Some(ref previous) = previous {
+                    for change in watch::notable_changes(previous, &status) {
+                        let title = format!("{} {}", term.abbreviation, mr_id);
+                        watch::notify(&title, change, desktop);
+                    }
+                }
+                previous = Some(status);
+            }
+            // Give up if the status can't be read at all, but ride out later blips
+            Err(error) if previous.is_none() => {
+                return Err(format!(
+                    "There was a problem getting the status of {} {}: {}",
+                    term.noun, mr_id, error
+                ))
+            }
+            Err(error) => debug!("Couldn't poll the status: {}", error),
         }
-        Err(error) => {
-            eprintln!("There was an error checking out the branch: {}", &error);
-            process::exit(1)
+        if !watch::sleep_unless_interrupted(secs) {
+            break;
         }
+    }
+    if redraw {
+        println!();
+    }
+    Ok(())
+}
+
+/// Open the pipeline (or checks) page of the MR with the given ID
+fn open_pipeline(remote_name: &str, mr_id: i64) -> Result<(), String> {
+    let mut remote = get_write_remote_or_describe(remote_name)?;
+    debug!("Found remote: {}", remote);
+    let term = remote.terminology();
+    info!("Getting the pipeline for {}: {}", term.abbreviation, mr_id);
+    let url = remote.get_pipeline_url(mr_id).map_err(|error| {
+        format!("Couldn't find the pipeline for {} {}: {}", term.noun, mr_id, error)
+    })?;
+    debug!("Got pipeline URL: {}", url);
+    browser::open(&url, |name| env::var(name).ok())
+}
+
+/// Check out the target branch of the MR with the given ID
+fn checkout_target(
+    remote_name: &str,
+    fetch_remote: &str,
+    mr_id: i64,
+    partial_fetch: bool,
+) -> Result<(), String> {
+    check_detached_head()?;
+    let mut remote = get_remote_or_describe(remote_name, true)?;
+    debug!("Found remote: {}", remote);
+    let term = remote.terminology();
+    info!("Getting target branch for {}: {}", term.abbreviation, mr_id);
+    let target_branch = remote
+        .get_req(mr_id)
+        .map_err(|error| format!("There was a problem getting {} {}: {}", term.noun, mr_id, error))?
+        .target_branch;
+    debug!("Got target branch name: {}", target_branch);
+    git::checkout_branch(fetch_remote, &target_branch, &target_branch, &[], partial_fetch)
+        .map_err(|error| format!("There was an error checking out the branch: {}", error))?;
+    eprintln!(
+        "Checked out {}, the target branch of {} {} (not the {} itself)",
+        target_branch, term.noun, mr_id, term.abbreviation
+    );
+    Ok(())
+}
+
+/// Parse a request ID (or pull request URL) given on the command line
+fn parse_request_id(remote_name: &str, arg: &str) -> Result<i64, String> {
+    match remotes::RequestQuery::parse(arg) {
+        Some(query) => find_request(remote_name, &query),
+        None => remotes::parse_request_id(arg, &get_origin(remote_name)),
+    }
+}
+
+/// Find the ID of the one open request matching an `author:terms` query
+fn find_request(remote_name: &str, query: &remotes::RequestQuery) -> Result<i64, String> {
+    let mut remote = get_remote_or_describe(remote_name, true)?;
+    debug!("Found remote: {}", remote);
+    let term = remote.terminology();
+    info!("Searching the open {}s for {:?}", term.noun, query);
+    let mut mrs = remote
+        .get_req_names(&Default::default())
+        .map_err(|error| format!("There was a problem listing the {}s: {}", term.noun, error))?;
+    mrs.retain(|mr| query.matches(mr));
+    let found = format!("match {:?}", query_description(query));
+    remotes::pick_found(&term, &mrs, &found, false, remotes::redact_content())
+}
+
+/// Find the ID of the one open request containing a commit, listing them if there are several
+fn find_request_for_commit(remote_name: &str, rev: &str) -> Result<i64, String> {
+    // The providers only look up full SHAs, so expand abbreviated ones when they're known locally
+    let sha = git::resolve_commit(rev).unwrap_or_else(|| String::from(rev));
+    let mut remote = get_remote_or_describe(remote_name, true)?;
+    debug!("Found remote: {}", remote);
+    let term = remote.terminology();
+    info!("Finding the open {}s containing {}", term.noun, sha);
+    let mrs = remote.get_reqs_for_commit(&sha).map_err(|error| {
+        format!(
+            "There was a problem finding the {}s containing {}: {}",
+            term.noun, rev, error
+        )
+    })?;
+    let found = format!("contain {}", rev);
+    remotes::pick_found(&term, &mrs, &found, false, remotes::redact_content())
+}
+
+/// Find the ID of the one open request from a branch, listing them if there are several. The
+/// branch doesn't need to exist locally; checking the request out fetches it by request ref.
+fn find_request_for_branch(remote_name: &str, branch: &str) -> Result<i64, String> {
+    let mut remote = get_remote_or_describe(remote_name, true)?;
+    debug!("Found remote: {}", remote);
+    let term = remote.terminology();
+    info!("Finding the open {}s from {}", term.noun, branch);
+    let mrs = remote
+        .get_req_names(&Default::default())
+        .map_err(|error| format!("There was a problem listing the {}s: {}", term.noun, error))?;
+    let mrs = remotes::requests_for_branch(mrs, branch);
+    // The same branch name can be proposed from several forks, so list their projects
+    let found = format!("are from the branch {}", branch);
+    remotes::pick_found(&term, &mrs, &found, true, remotes::redact_content())
+}
+
+/// Describe a request query the way it was typed
+fn query_description(query: &remotes::RequestQuery) -> String {
+    format!("{}:{}", query.author, query.terms.join(" "))
+}
+
+/// Find the request the current CI job runs for
+fn detect_ci_request_id() -> Result<i64, String> {
+    match ci::detect_request_id(|name| env::var(name).ok()) {
+        Some((variable, mr_id)) => {
+            info!("Using request {} from {}", mr_id, variable);
+            Ok(mr_id)
+        }
+        None => Err(String::from(
+            "No request ID was given, and none could be found in the CI environment",
+        )),
+    }
+}
+
+/// Parse the request IDs read from stdin, one per line, ignoring blank lines
+fn parse_request_ids(remote_name: &str, input: &str) -> Vec<Result<i64, String>> {
+    input
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(|line| parse_request_id(remote_name, line))
+        .collect()
+}
+
+/// Run an operation on each request ID read from stdin, carrying on past failures. Operations
+/// with a `verb` touch branches, so they're confirmed first when there are more requests than
+/// `req.confirmthreshold`, unless `assume_yes` is set.
+fn run_stdin_batch<F>(
+    remote_name: &str,
+    operation: &str,
+    verb: Option<&str>,
+    assume_yes: bool,
+    action: F,
+) -> Result<(), String>
+where
+    F: Fn(i64) -> Result<(), String>,
+{
+    let mut input = String::new();
+    io::stdin()
+        .read_to_string(&mut input)
+        .map_err(|error| format!("There was a problem reading from stdin: {}", error))?;
+    let ids = parse_request_ids(remote_name, &input);
+    if ids.is_empty() {
+        eprintln!("No request IDs given on stdin");
+        return Ok(());
+    }
+    let total = ids.len();
+    if let Some(verb) = verb {
+        confirm_batch(verb, total, assume_yes)?;
+    }
+    let mut failures = 0;
+    for id in ids {
+        let (mr_id, result) = match id {
+            Ok(mr_id) => (Some(mr_id), action(mr_id)),
+            Err(error) => (None, Err(error)),
+        };
+        audit::record(operation, remote_name, mr_id, &result);
+        if let Err(error) = result {
+            failures += 1;
+            match mr_id {
+                Some(mr_id) => eprintln!("{}: {}", mr_id, error),
+                None => eprintln!("{}", error),
+            }
+        }
+    }
+    if failures > 0 {
+        return Err(format!("{} of {} requests failed", failures, total));
+    }
+    Ok(())
+}
+
+/// Check that a batch of `count` requests can go ahead, asking on the terminal if it's over
+/// the threshold. Stdin holds the request IDs, so the answer is read from the terminal itself.
+fn confirm_batch(verb: &str, count: usize, assume_yes: bool) -> Result<(), String> {
+    let threshold = batch::confirm_threshold(git::get_config("confirmthreshold"))?;
+    let terminal = if atty::is(atty::Stream::Stderr) {
+        open_terminal()
+    } else {
+        None
     };
+    match batch::confirmation(verb, count, threshold, assume_yes, terminal.is_some()) {
+        batch::Confirmation::NotNeeded => Ok(()),
+        batch::Confirmation::Ask(prompt) => {
+            if confirm_from(&prompt, &mut io::BufReader::new(terminal.unwrap())) {
+                Ok(())
+            } else {
+                Err(String::from("Aborted"))
+            }
+        }
+        batch::Confirmation::Refuse(error) => Err(error),
+    }
+}
+
+/// Open the controlling terminal for reading, if there is one
+fn open_terminal() -> Option<std::fs::File> {
+    let path = if cfg!(windows) { "CONIN$" } else { "/dev/tty" };
+    std::fs::File::open(path).ok()
+}
+
+/// Ask the user a yes/no question, defaulting to no
+fn confirm(prompt: &str) -> bool {
+    confirm_from(prompt, &mut io::stdin().lock())
+}
+
+/// Ask the user a yes/no question, reading the answer from `input`
+fn confirm_from<R: BufRead>(prompt: &str, input: &mut R) -> bool {
+    eprint!("{} [y/N] ", prompt);
+    let _ = io::stderr().flush();
+    let mut answer = String::new();
+    if input.read_line(&mut answer).is_err() {
+        return false;
+    }
+    matches!(answer.trim().to_lowercase().as_ref(), "y" | "yes")
+}
+
+/// Request reviews of the MR with the given ID
+fn request_review(
+    remote_name: &str,
+    mr_id: i64,
+    usernames: &[String],
+    assume_yes: bool,
+) -> Result<(), String> {
+    let mut remote = get_write_remote_or_describe(remote_name)?;
+    debug!("Found remote: {}", remote);
+    let term = remote.terminology();
+    let names: Vec<&str> = usernames
+        .iter()
+        .map(|name| remotes::normalize_username(name))
+        .collect();
+    let prompt = format!(
+        "Request review of {} {} from {}?",
+        term.noun,
+        mr_id,
+        names.join(", ")
+    );
+    if !assume_yes && !confirm(&prompt) {
+        return Err(String::from("Aborted"));
+    }
+    remote.request_review(mr_id, usernames).map_err(|error| {
+        format!(
+            "There was a problem requesting review of {} {}: {}",
+            term.noun, mr_id, error
+        )
+    })?;
+    eprintln!("Review requested!");
+    Ok(())
 }
 
 /// Clear the API key for the current domain
-fn clear_domain_key() {
+fn clear_domain_key(remote_name: &str) -> Result<(), String> {
     trace!("Deleting domain key");
-    let mut remote = get_remote_hard(false);
+    let mut remote = get_remote_or_describe(remote_name, false)?;
     let deleted = match git::delete_req_config(&remote.get_domain(), "apikey") {
         Ok(_) => Ok(true),
         Err(e) => match e.code() {
@@ -76,55 +795,349 @@ fn clear_domain_key() {
         },
     };
     match deleted {
-        Ok(_) => eprintln!("Domain key deleted!"),
+        Ok(_) => {
+            eprintln!("Domain key deleted!");
+            Ok(())
+        }
         Err(e) => {
             error!("Git Config error: {}", e);
-            eprintln!(
+            Err(format!(
                 "There was an error deleting the domain key: {}",
                 e.message()
-            );
-            process::exit(1)
+            ))
         }
     }
 }
 
 /// Set the API key for the current domain
-fn set_domain_key(new_key: &str) {
+fn set_domain_key(remote_name: &str, new_key: &str) -> Result<(), String> {
     trace!("Setting domain key: {}", new_key);
-    let mut remote = get_remote_hard(false);
+    let mut remote = get_remote_or_describe(remote_name, false)?;
     git::set_req_config(&remote.get_domain(), "apikey", new_key);
     eprintln!("Domain key changed!");
+    Ok(())
 }
 
 /// Delete the project ID entry
-fn clear_project_id() {
+fn clear_project_id() -> Result<(), String> {
     trace!("Deleting project ID");
     git::delete_config("projectid");
     eprintln!("Project ID cleared!");
+    Ok(())
+}
+
+/// Forget the cached project ID for the current project and resolve it again
+fn refresh_project_id(remote_name: &str) -> Result<(), String> {
+    trace!("Refreshing project ID");
+    let origin = get_origin(remote_name);
+    let domain = remotes::get_domain(&origin)?;
+    if remotes::Provider::detect(domain) != remotes::Provider::GitLab {
+        return Err(String::from("Only GitLab project IDs need to be resolved"));
+    }
+    let namespace = remotes::gitlab::get_gitlab_project_namespace(&origin)
+        .ok_or("Could not parse the GitLab project namespace from the origin.")?;
+    let name = remotes::gitlab::get_gitlab_project_name(&origin);
+    if let Err(e) = remotes::gitlab::clear_cached_project_id(domain, &namespace, &name) {
+        if e.code() != ErrorCode::NotFound {
+            return Err(format!(
+                "There was an error clearing the cached project ID: {}",
+                e.message()
+            ));
+        }
+    }
+    // Look the project up rather than taking the ID the lock pins
+    let mut remote = get_origin_for(remote_name, remotes::UrlPurpose::Read)
+        .and_then(|origin| remotes::get_unlocked_remote(&origin, false))
+        .map_err(|error| format!("There was a problem finding the remote Git repo: {}", error))?;
+    let project_id = remote.get_project_id()?;
+    eprintln!("Project ID resolved: {}", project_id);
+    if git::get_config("projectid").is_some() {
+        eprintln!("Note: req.projectid is set for this repository and takes precedence");
+    } else if let Some(lock) =
+        remotes::lock::load(&origin).filter(|lock| lock.pinned_project_id() != Some(project_id))
+    {
+        eprintln!(
+            "Note: {} pins project ID {} and takes precedence; run --update-lock to refresh it",
+            remotes::lock::LOCKFILE_NAME,
+            lock.project_id
+        );
+    }
+    Ok(())
+}
+
+/// Record how the remote's project resolves in the lockfile
+fn update_lock(remote_name: &str) -> Result<(), String> {
+    trace!("Updating the lockfile");
+    let origin = get_origin(remote_name);
+    let lock = remotes::resolve_lock(&origin)
+        .map_err(|error| format!("There was a problem resolving the project: {}", error))?;
+    let path = remotes::lock::write(&lock)?;
+    eprintln!(
+        "Locked {} to {} project {} in {}",
+        lock.project,
+        lock.provider,
+        lock.project_id,
+        path.display()
+    );
+    Ok(())
 }
 
 /// Set the project ID
-fn set_project_id(new_id: &str) {
+fn set_project_id(new_id: &str) -> Result<(), String> {
     trace!("Setting project ID: {}", new_id);
     git::set_config("projectid", new_id);
     eprintln!("New project ID set!");
+    Ok(())
+}
+
+/// Print how the remote for the current project is detected
+fn explain_remote(remote_name: &str) -> Result<(), String> {
+    let origin = get_origin_for(remote_name, remotes::UrlPurpose::Read)?;
+    let write_origin = get_origin_for(remote_name, remotes::UrlPurpose::Write)?;
+    let mut tw = TabWriter::new(io::stdout()).padding(2);
+    writeln!(&mut tw, "remote:\t{}", remote_name).unwrap();
+    for (step, detail) in remotes::explain_remote(&origin) {
+        writeln!(&mut tw, "{}:\t{}", step, detail).unwrap();
+    }
+    if write_origin != origin {
+        let push_url = remotes::redact_origin(&write_origin);
+        writeln!(&mut tw, "writes:\tvia the push URL {} (req.pushurl)", push_url).unwrap();
+    }
+    tw.flush().unwrap();
+    Ok(())
 }
 
 /// Print the open requests
-fn list_open_requests() {
-    info!("Getting open requests");
-    let mut remote = get_remote_hard(true);
+fn list_open_requests(
+    remote_name: &str,
+    opts: &remotes::ListOptions,
+    show_behind: bool,
+    oneline: bool,
+    limit: Option<usize>,
+    count_by: Option<remotes::CountField>,
+    stacked: bool,
+) -> Result<(), String> {
+    let mut remote = get_remote_or_describe(remote_name, true)?;
     debug!("Found remote: {}", remote);
-    let mrs = remote.get_req_names().unwrap();
+    let term = remote.terminology();
+    info!("Getting open {}s", term.noun);
+    let mut mrs = remote
+        .get_req_names(opts)
+        .map_err(|error| format!("There was a problem listing the {}s: {}", term.noun, error))?;
+    // Not every provider filters drafts, labels and assignees server-side, and none filter by
+    // target glob
+    mrs.retain(|mr| {
+        opts.matches_drafts(mr)
+            && opts.matches_targets(mr)
+            && opts.matches_labels(mr)
+            && opts.matches_assignee(mr)
+    });
+    if !opts.paths.is_empty() {
+        mrs = filter_by_paths(&mut remote, mrs, opts, limit)?;
+    }
+    if let Some(limit) = limit {
+        mrs.truncate(limit);
+    }
+    if mrs.is_empty() {
+        eprintln!("No open {}s found", term.noun);
+        return Ok(());
+    }
+    if let Some(field) = count_by {
+        let mut tw = TabWriter::new(io::stdout()).padding(4);
+        for (value, count) in remotes::count_by(&mrs, field) {
+            writeln!(&mut tw, "{}\t{}", count, value).unwrap();
+        }
+        tw.flush().unwrap();
+        return Ok(());
+    }
+    if oneline {
+        let width = terminal_width();
+        for mr in &mrs {
+            println!("{}", mr.oneline(width));
+        }
+        return Ok(());
+    }
+    // Group under the configured base branches, in the order they're configured, unless the
+    // requests are shown as stacks instead
+    let mut drawings = vec![];
+    if stacked {
+        let arranged = stack::arrange(&mrs);
+        let mut slots: Vec<Option<remotes::MergeRequest>> = mrs.into_iter().map(Some).collect();
+        mrs = vec![];
+        for (index, drawing) in arranged {
+            mrs.extend(slots[index].take());
+            drawings.push(drawing);
+        }
+    }
+    let base_branches = if stacked {
+        vec![]
+    } else {
+        configured_base_branches()
+    };
+    let group_of = |mr: &remotes::MergeRequest| {
+        remotes::base_branch_group(&base_branches, &mr.target_branch)
+    };
+    let group_index = |mr: &remotes::MergeRequest| {
+        group_of(mr)
+            .and_then(|group| base_branches.iter().position(|pattern| pattern == group))
+            .unwrap_or(base_branches.len())
+    };
+    mrs.sort_by_key(|mr| group_index(mr));
+    let behind_counts = if show_behind {
+        info!("Counting commits behind the target for {} {}s", mrs.len(), term.noun);
+        remote.get_behind_counts(&mrs).unwrap_or_else(|error| {
+            eprintln!("Warning: {}; listing without the counts", error);
+            vec![]
+        })
+    } else {
+        vec![]
+    };
     let mut tw = TabWriter::new(io::stdout()).padding(4);
-    for mr in &mrs {
+    let mut current_group = None;
+    for (index, mr) in mrs.iter().enumerate() {
+        if !base_branches.is_empty() && (index == 0 || current_group != group_of(mr)) {
+            current_group = group_of(mr);
+            let heading = current_group.unwrap_or("other targets");
+            let gap = if index == 0 { "" } else { "\n" };
+            writeln!(&mut tw, "{}{}:", gap, heading).unwrap();
+        }
+        let mut title = match mr.source_project {
+            Some(ref project) => format!("{} (from fork {})", mr.title, project),
+            None => mr.title.to_string(),
+        };
+        if !mr.assignees.is_empty() {
+            title = format!("{} (assigned to {})", title, mr.assignees.join(", "));
+        }
+        match behind_counts.get(index) {
+            Some(Ok(0)) | None => {}
+            Some(Ok(behind)) => {
+                title = format!("{} ({} behind {})", title, behind, mr.target_branch);
+            }
+            Some(Err(error)) => eprintln!(
+                "Warning: couldn't tell how far {} {} is behind {}: {}",
+                term.noun, mr.id, mr.target_branch, error
+            ),
+        }
+        let drawing = drawings.get(index).map_or("", String::as_str);
         if remote.has_useful_branch_names() {
-            writeln!(&mut tw, "{}\t{}\t{}", mr.id, mr.source_branch, mr.title).unwrap();
+            writeln!(&mut tw, "{}{}\t{}\t{}", drawing, mr.id, mr.source_branch, title).unwrap();
         } else {
-            writeln!(&mut tw, "{}\t{}", mr.id, mr.title).unwrap();
+            writeln!(&mut tw, "{}{}\t{}", drawing, mr.id, title).unwrap();
         }
     }
     tw.flush().unwrap();
+    Ok(())
+}
+
+/// Get the width of the terminal stdout is connected to, if it's one and `$COLUMNS` says
+fn terminal_width() -> Option<usize> {
+    if !atty::is(atty::Stream::Stdout) {
+        return None;
+    }
+    env::var("COLUMNS").ok().and_then(|columns| columns.parse().ok())
+}
+
+/// Get the values given for a repeatable argument
+fn values_of(matches: &clap::ArgMatches, name: &str) -> Vec<String> {
+    matches
+        .values_of(name)
+        .map(|values| values.map(String::from).collect())
+        .unwrap_or_default()
+}
+
+/// Get the base branch patterns to group the listing by: those given with `--base`, or if it's
+/// given without any, `req.basebranches`
+fn base_targets(matches: &clap::ArgMatches) -> Vec<String> {
+    match matches.values_of("BASE") {
+        Some(patterns) if patterns.len() > 0 => patterns.map(String::from).collect(),
+        Some(_) => configured_base_branches(),
+        None => vec![],
+    }
+}
+
+/// Get the glob patterns configured in `req.basebranches`
+fn configured_base_branches() -> Vec<String> {
+    git::get_config("basebranches")
+        .map(|value| remotes::parse_base_branches(&value))
+        .unwrap_or_default()
+}
+
+/// Print raw provider JSON, indented unless `compact` is set
+fn print_raw(value: &serde_json::Value, compact: bool) -> Result<(), String> {
+    let json = if compact {
+        serde_json::to_string(value)
+    } else {
+        serde_json::to_string_pretty(value)
+    };
+    println!("{}", json.map_err(|error| format!("Could not format the JSON: {}", error))?);
+    Ok(())
+}
+
+/// Print the provider's JSON for the MR with the given ID
+fn show_raw_request(remote_name: &str, mr_id: i64, compact: bool) -> Result<(), String> {
+    let mut remote = get_remote_or_describe(remote_name, true)?;
+    debug!("Found remote: {}", remote);
+    let term = remote.terminology();
+    let value = remote.get_req_raw(mr_id).map_err(|error| {
+        format!("There was a problem getting {} {}: {}", term.noun, mr_id, error)
+    })?;
+    print_raw(&value, compact)
+}
+
+/// Keep the requests changing files that match the path globs, fetching each request's files a
+/// batch at a time and stopping once there are enough matches
+fn filter_by_paths(
+    remote: &mut Box<dyn remotes::Remote>,
+    mrs: Vec<remotes::MergeRequest>,
+    opts: &remotes::ListOptions,
+    limit: Option<usize>,
+) -> Result<Vec<remotes::MergeRequest>, String> {
+    let term = remote.terminology();
+    if mrs.len() > remotes::MAX_CONCURRENT_REQUESTS && limit.is_none() {
+        eprintln!(
+            "Warning: --path fetches the changed files of all {} {}s; pass --limit to stop early",
+            mrs.len(),
+            term.noun
+        );
+    }
+    let mut matching = vec![];
+    let mut remaining = mrs.into_iter();
+    loop {
+        let batch: Vec<_> = remaining
+            .by_ref()
+            .take(remotes::MAX_CONCURRENT_REQUESTS)
+            .collect();
+        if batch.is_empty() || limit.is_some_and(|limit| matching.len() >= limit) {
+            return Ok(matching);
+        }
+        info!("Getting the changed files of {} {}s", batch.len(), term.noun);
+        let changed_files = remote.get_changed_files(&batch)?;
+        for (mr, files) in batch.into_iter().zip(changed_files) {
+            match files {
+                Ok(ref files) if opts.matches_paths(files) => matching.push(mr),
+                Ok(_) => {}
+                Err(error) => eprintln!(
+                    "Warning: couldn't get the files {} {} changes: {}",
+                    term.noun, mr.id, error
+                ),
+            }
+        }
+    }
+}
+
+/// Print the provider's JSON listing the open requests
+fn list_raw_requests(
+    remote_name: &str,
+    opts: &remotes::ListOptions,
+    compact: bool,
+) -> Result<(), String> {
+    let mut remote = get_remote_or_describe(remote_name, true)?;
+    debug!("Found remote: {}", remote);
+    let term = remote.terminology();
+    let value = remote
+        .get_req_names_raw(opts)
+        .map_err(|error| format!("There was a problem listing the {}s: {}", term.noun, error))?;
+    print_raw(&value, compact)
 }
 
 /// Do the thing
@@ -146,6 +1159,208 @@ fn main() {
              .help("List all open requests against the repository")
              .takes_value(false)
              .required(false))
+        .arg(Arg::with_name("PARAM")
+             .long("param")
+             .value_name("KEY=VALUE")
+             .help("Pass an extra query parameter to the provider when listing requests (repeatable)")
+             .takes_value(true)
+             .multiple(true)
+             .number_of_values(1)
+             .requires("LIST_MR")
+             .validator(|param| remotes::parse_query_param(&param).map(|_| ())))
+        .arg(Arg::with_name("NO_DRAFTS")
+             .long("no-drafts")
+             .help("Leave draft (work in progress) requests out of the listing")
+             .takes_value(false)
+             .required(false)
+             .requires("LIST_MR"))
+        .arg(Arg::with_name("DRAFTS_ONLY")
+             .long("drafts-only")
+             .help("List only draft (work in progress) requests")
+             .takes_value(false)
+             .required(false)
+             .requires("LIST_MR")
+             .conflicts_with("NO_DRAFTS"))
+        .arg(Arg::with_name("LABEL")
+             .long("label")
+             .value_name("NAME")
+             .help("List only requests with the label (repeatable; all must match)")
+             .takes_value(true)
+             .multiple(true)
+             .number_of_values(1)
+             .required(false)
+             .requires("LIST_MR")
+             .conflicts_with("RAW"))
+        .arg(Arg::with_name("EXCLUDE_LABEL")
+             .long("exclude-label")
+             .value_name("NAME")
+             .help("Leave requests with the label out of the listing (repeatable)")
+             .takes_value(true)
+             .multiple(true)
+             .number_of_values(1)
+             .required(false)
+             .requires("LIST_MR")
+             .conflicts_with("RAW"))
+        .arg(Arg::with_name("ASSIGNEE")
+             .long("assignee")
+             .value_name("USER")
+             .help("List only requests assigned to the user")
+             .takes_value(true)
+             .required(false)
+             .requires("LIST_MR")
+             .conflicts_with("RAW"))
+        .arg(Arg::with_name("PATH")
+             .long("path")
+             .value_name("GLOB")
+             .help("List only requests changing files matching GLOB (repeatable; fetches each request's files)")
+             .takes_value(true)
+             .multiple(true)
+             .number_of_values(1)
+             .required(false)
+             .requires("LIST_MR")
+             .conflicts_with("RAW"))
+        .arg(Arg::with_name("LIMIT")
+             .long("limit")
+             .value_name("N")
+             .help("List at most N requests")
+             .takes_value(true)
+             .required(false)
+             .requires("LIST_MR")
+             .conflicts_with("RAW")
+             .validator(|limit| limit.parse::<usize>().map(|_| ()).map_err(|_| format!("{} isn't a number", limit))))
+        .arg(Arg::with_name("BASE")
+             .long("base")
+             .value_name("PATTERN")
+             .help("List only requests targeting branches matching PATTERN (defaults to req.basebranches)")
+             .takes_value(true)
+             .multiple(true)
+             .min_values(0)
+             .require_delimiter(true)
+             .required(false)
+             .requires("LIST_MR")
+             .conflicts_with("RAW"))
+        .arg(Arg::with_name("ONELINE")
+             .long("oneline")
+             .help("List each request as just #<id> <title>, cut to the terminal's width")
+             .takes_value(false)
+             .required(false)
+             .requires("LIST_MR")
+             .conflicts_with_all(&["RAW", "BEHIND"]))
+        .arg(Arg::with_name("COUNT_BY")
+             .long("count-by")
+             .value_name("FIELD")
+             .help("Print how many of the listed requests there are for each author, target or label")
+             .takes_value(true)
+             .possible_values(remotes::CountField::NAMES)
+             .required(false)
+             .requires("LIST_MR")
+             .conflicts_with_all(&["RAW", "ONELINE", "BEHIND"]))
+        .arg(Arg::with_name("STACK")
+             .long("stack")
+             .help("Show each listed request under the request whose branch it targets")
+             .takes_value(false)
+             .required(false)
+             .requires("LIST_MR")
+             .conflicts_with_all(&["RAW", "ONELINE", "COUNT_BY"]))
+        .arg(Arg::with_name("BEHIND")
+             .long("behind")
+             .help("Show how many commits each listed request is behind its target branch")
+             .takes_value(false)
+             .required(false)
+             .requires("LIST_MR")
+             .conflicts_with("RAW"))
+        .arg(Arg::with_name("RAW")
+             .long("raw")
+             .help("Print the provider's JSON for the request (or with --list, the listing) instead")
+             .takes_value(false)
+             .required(false)
+             .conflicts_with_all(&["TARGET", "PICK", "CHECK", "REQUEST_REVIEW", "STDIN"]))
+        .arg(Arg::with_name("COMPACT")
+             .long("compact")
+             .help("Print --raw JSON on a single line instead of indented")
+             .takes_value(false)
+             .required(false)
+             .requires("RAW"))
+        .arg(Arg::with_name("TARGET")
+             .long("target")
+             .help("Check out the branch the request targets instead of the request itself")
+             .takes_value(false)
+             .required(false))
+        .arg(Arg::with_name("PICK")
+             .long("pick")
+             .help("Fetch the request's branch without checking it out, and print its name")
+             .takes_value(false)
+             .required(false)
+             .conflicts_with("TARGET"))
+        .arg(Arg::with_name("CHECK")
+             .long("check")
+             .help("Report whether the request's local branch is behind the remote, without checking it out")
+             .takes_value(false)
+             .required(false)
+             .conflicts_with_all(&["TARGET", "PICK"]))
+        .arg(Arg::with_name("REQUIRE")
+             .long("require")
+             .value_name("CONDITION")
+             .help("With --check, exit non-zero unless the request meets the condition (repeatable)")
+             .takes_value(true)
+             .multiple(true)
+             .number_of_values(1)
+             .possible_values(remotes::Requirement::NAMES)
+             .requires("CHECK"))
+        .arg(Arg::with_name("WATCH")
+             .long("watch")
+             .help("Keep showing the request's pipeline, approval and merge status, notifying when it changes")
+             .takes_value(false)
+             .required(false)
+             .conflicts_with_all(&["TARGET", "PICK", "CHECK", "RAW", "STDIN", "PIPELINE", "REVIEW"]))
+        .arg(Arg::with_name("INTERVAL")
+             .long("interval")
+             .value_name("SECONDS")
+             .help("How often --watch polls (defaults to req.watchinterval, or 30; at least 10)")
+             .takes_value(true)
+             .required(false)
+             .requires("WATCH"))
+        .arg(Arg::with_name("REVIEW")
+             .long("review")
+             .help("Check the request out, then show its changes against the target with req.difftool (or git diff)")
+             .takes_value(false)
+             .required(false)
+             .conflicts_with_all(&["TARGET", "PICK", "CHECK", "RAW", "STDIN", "PIPELINE"]))
+        .arg(Arg::with_name("AS")
+             .long("as")
+             .value_name("BRANCH")
+             .help("Check the request out into BRANCH instead of the usual branch name")
+             .takes_value(true)
+             .required(false)
+             .conflicts_with_all(&["FLAGS", "STDIN", "TARGET", "CHECK", "RAW", "PIPELINE", "WATCH", "REQUEST_REVIEW", "NEW_DOMAIN_KEY"]))
+        .arg(Arg::with_name("FORCE")
+             .long("force")
+             .help("With --as or --pick, reset the branch to the request if it already exists")
+             .takes_value(false)
+             .required(false)
+             .requires("FORCEABLE"))
+        .arg(Arg::with_name("PIPELINE")
+             .long("pipeline")
+             .help("Open the request's pipeline (on GitHub, its checks) in the browser")
+             .takes_value(false)
+             .required(false)
+             .conflicts_with_all(&["TARGET", "PICK", "CHECK", "RAW", "STDIN"]))
+        .arg(Arg::with_name("REQUEST_REVIEW")
+             .long("request-review")
+             .help("Request reviews of the request from the given users")
+             .takes_value(false)
+             .required(false)
+             .requires("REVIEWERS"))
+        .arg(Arg::with_name("EXPLAIN")
+             .long("explain")
+             .help("Explain how the provider and project for the repository are detected")
+             .takes_value(false)
+             .required(false))
+        .arg(Arg::with_name("PROMPT")
+             .long("prompt")
+             .help("Print a prompt segment like req:42 if the current branch is for a request")
+             .takes_value(false)
+             .required(false))
         .arg(Arg::with_name("NEW_PROJECT_ID")
              .long("set-project-id")
              .value_name("PROJECT_ID")
@@ -157,6 +1372,16 @@ fn main() {
              .help("Clear the project ID for the current repository")
              .required(false)
              .takes_value(false))
+        .arg(Arg::with_name("REFRESH_PROJECT_ID")
+             .long("refresh-id")
+             .help("Resolve the project ID for the current repository again, replacing the cached one")
+             .required(false)
+             .takes_value(false))
+        .arg(Arg::with_name("UPDATE_LOCK")
+             .long("update-lock")
+             .help("Resolve the provider and project again and record them in git-req.lock")
+             .required(false)
+             .takes_value(false))
         .arg(Arg::with_name("CLEAR_DOMAIN_KEY")
              .long("clear-domain-key")
              .help("Clear the API key for the current repository's domain")
@@ -167,24 +1392,262 @@ fn main() {
              .help("Set the API key for the current repository's domain")
              .required(false)
              .takes_value(true))
+        .group(ArgGroup::with_name("FORCEABLE")
+               .args(&["AS", "PICK"]))
         .group(ArgGroup::with_name("FLAGS")
-               .args(&vec!["NEW_PROJECT_ID", "LIST_MR", "CLEAR_PROJECT_ID", "CLEAR_DOMAIN_KEY"]))
+               .args(&vec!["NEW_PROJECT_ID", "LIST_MR", "CLEAR_PROJECT_ID", "CLEAR_DOMAIN_KEY", "EXPLAIN", "REFRESH_PROJECT_ID", "UPDATE_LOCK", "PROMPT"]))
+        .arg(Arg::with_name("REMOTE")
+             .long("remote")
+             .value_name("NAME")
+             .help("The git remote to query for requests (defaults to origin)")
+             .takes_value(true)
+             .required(false))
+        .arg(Arg::with_name("FETCH_REMOTE")
+             .long("fetch-remote")
+             .value_name("NAME")
+             .help("The git remote to fetch request branches from (defaults to the queried remote)")
+             .takes_value(true)
+             .required(false))
+        .arg(Arg::with_name("STDIN")
+             .long("stdin")
+             .help("Read request IDs from stdin, one per line (same as passing - as the ID)")
+             .takes_value(false)
+             .required(false)
+             .conflicts_with_all(&["FLAGS", "REQUEST_REVIEW"]))
+        .arg(Arg::with_name("YES")
+             .long("yes")
+             .short("y")
+             .help("Don't ask before requesting reviews or acting on more requests from stdin than req.confirmthreshold")
+             .takes_value(false)
+             .required(false))
+        .arg(Arg::with_name("FOR_SHA")
+             .long("for-sha")
+             .value_name("SHA")
+             .help("Act on the open request containing the commit, or list them if there are several")
+             .takes_value(true)
+             .required(false)
+             .conflicts_with_all(&["FLAGS", "STDIN", "REQUEST_ID"]))
+        .arg(Arg::with_name("BRANCH")
+             .long("branch")
+             .value_name("NAME")
+             .help("Act on the open request from the branch, which doesn't need to have been fetched")
+             .takes_value(true)
+             .required(false)
+             .conflicts_with_all(&["FLAGS", "STDIN", "REQUEST_ID", "FOR_SHA"]))
         .arg(Arg::with_name("REQUEST_ID")
-             .required(true)
-             .conflicts_with_all(&["FLAGS"])
+             .help("The request to act on; detected from the CI environment if omitted")
+             .conflicts_with_all(&["FLAGS", "STDIN"])
              .index(1))
+        .arg(Arg::with_name("REVIEWERS")
+             .help("The users to request reviews from, with --request-review")
+             .multiple(true)
+             .requires("REQUEST_REVIEW")
+             .index(2))
         .get_matches();
-    if let Some(project_id) = matches.value_of("NEW_PROJECT_ID") {
-        set_project_id(project_id);
+    // Prompts run this constantly, so skip everything that could be slow or ask questions
+    if matches.is_present("PROMPT") {
+        print_prompt_segment();
+        return;
+    }
+    // Setting or clearing the project ID doesn't use the remote, so don't ask for one
+    let uses_remote =
+        !matches.is_present("NEW_PROJECT_ID") && !matches.is_present("CLEAR_PROJECT_ID");
+    let remote_name = if uses_remote {
+        match resolve_remote_name(matches.value_of("REMOTE")) {
+            Ok(remote_name) => remote_name,
+            Err(error) => {
+                eprintln!("{}", error);
+                process::exit(1);
+            }
+        }
+    } else {
+        String::from(matches.value_of("REMOTE").unwrap_or("origin"))
+    };
+    let remote_name = remote_name.as_str();
+    let fetch_remote = match matches.value_of("FETCH_REMOTE") {
+        Some(name) if git::find_remote_url(name).is_none() => {
+            eprintln!("There's no remote named {}", name);
+            process::exit(1);
+        }
+        Some(name) => name,
+        None => remote_name,
+    };
+    let with_request_id = |operation, action: &dyn Fn(i64) -> Result<(), String>| {
+        let mr_id = match (
+            matches.value_of("REQUEST_ID"),
+            matches.value_of("FOR_SHA"),
+            matches.value_of("BRANCH"),
+        ) {
+            (Some(arg), _, _) => parse_request_id(remote_name, arg),
+            (None, Some(sha), _) => find_request_for_commit(remote_name, sha),
+            (None, None, Some(branch)) => find_request_for_branch(remote_name, branch),
+            (None, None, None) => detect_ci_request_id(),
+        };
+        match mr_id {
+            Ok(mr_id) => (operation, Some(mr_id), action(mr_id)),
+            Err(error) => (operation, None, Err(error)),
+        }
+    };
+    let requirements: Vec<remotes::Requirement> = matches
+        .values_of("REQUIRE")
+        .map(|names| {
+            names
+                .map(|name| remotes::Requirement::parse(name).unwrap())
+                .collect()
+        })
+        .unwrap_or_default();
+    let assume_yes = matches.is_present("YES");
+    let branch_name = matches.value_of("AS");
+    let force = matches.is_present("FORCE");
+    let partial_fetch = partial_fetch_setting();
+    let from_stdin = matches.is_present("STDIN") || matches.value_of("REQUEST_ID") == Some("-");
+    let (operation, mr_id, result) = if let Some(project_id) = matches.value_of("NEW_PROJECT_ID") {
+        ("set-project-id", None, set_project_id(project_id))
     } else if matches.is_present("CLEAR_PROJECT_ID") {
-        clear_project_id();
+        ("clear-project-id", None, clear_project_id())
+    } else if matches.is_present("REFRESH_PROJECT_ID") {
+        ("refresh-project-id", None, refresh_project_id(remote_name))
+    } else if matches.is_present("UPDATE_LOCK") {
+        ("update-lock", None, update_lock(remote_name))
+    } else if matches.is_present("LIST_MR")
+        && matches.is_present("BASE")
+        && base_targets(&matches).is_empty()
+    {
+        let operation = if matches.is_present("RAW") { "list-raw" } else { "list" };
+        let error = "No base branches given. Pass --base PATTERN or set req.basebranches";
+        (operation, None, Err(String::from(error)))
     } else if matches.is_present("LIST_MR") {
-        list_open_requests();
+        let targets = base_targets(&matches);
+        let opts = remotes::ListOptions {
+            params: matches
+                .values_of("PARAM")
+                .map(|params| {
+                    params
+                        .map(|param| remotes::parse_query_param(param).unwrap())
+                        .collect()
+                })
+                .unwrap_or_default(),
+            drafts: if matches.is_present("NO_DRAFTS") {
+                remotes::DraftFilter::Exclude
+            } else if matches.is_present("DRAFTS_ONLY") {
+                remotes::DraftFilter::Only
+            } else {
+                remotes::DraftFilter::All
+            },
+            targets,
+            labels: values_of(&matches, "LABEL"),
+            exclude_labels: values_of(&matches, "EXCLUDE_LABEL"),
+            assignee: matches.value_of("ASSIGNEE").map(String::from),
+            paths: values_of(&matches, "PATH"),
+        };
+        if matches.is_present("RAW") {
+            let result = list_raw_requests(remote_name, &opts, matches.is_present("COMPACT"));
+            ("list-raw", None, result)
+        } else {
+            let result = list_open_requests(
+                remote_name,
+                &opts,
+                matches.is_present("BEHIND"),
+                matches.is_present("ONELINE"),
+                matches.value_of("LIMIT").map(|limit| limit.parse().unwrap()),
+                matches
+                    .value_of("COUNT_BY")
+                    .map(|field| remotes::CountField::parse(field).unwrap()),
+                matches.is_present("STACK"),
+            );
+            ("list", None, result)
+        }
+    } else if matches.is_present("EXPLAIN") {
+        ("explain", None, explain_remote(remote_name))
     } else if matches.is_present("CLEAR_DOMAIN_KEY") {
-        clear_domain_key();
+        ("clear-domain-key", None, clear_domain_key(remote_name))
     } else if let Some(domain_key) = matches.value_of("NEW_DOMAIN_KEY") {
-        set_domain_key(domain_key);
+        ("set-domain-key", None, set_domain_key(remote_name, domain_key))
+    } else if matches.is_present("REQUEST_REVIEW") && from_stdin {
+        (
+            "request-review",
+            None,
+            Err(String::from("Request IDs can't be read from stdin with --request-review")),
+        )
+    } else if matches.is_present("REQUEST_REVIEW") {
+        let usernames: Vec<String> = matches
+            .values_of("REVIEWERS")
+            .unwrap()
+            .map(String::from)
+            .collect();
+        with_request_id("request-review", &|mr_id| {
+            request_review(remote_name, mr_id, &usernames, assume_yes)
+        })
+    } else if matches.is_present("RAW") {
+        let compact = matches.is_present("COMPACT");
+        with_request_id("raw", &|mr_id| show_raw_request(remote_name, mr_id, compact))
+    } else if matches.is_present("REQUIRE") && from_stdin {
+        let result = run_stdin_batch(remote_name, "check-require", None, assume_yes, |mr_id| {
+            check_requirements(remote_name, mr_id, &requirements)
+        });
+        ("batch-check-require", None, result)
+    } else if matches.is_present("REQUIRE") {
+        with_request_id("check-require", &|mr_id| {
+            check_requirements(remote_name, mr_id, &requirements)
+        })
+    } else if matches.is_present("PIPELINE") {
+        with_request_id("pipeline", &|mr_id| open_pipeline(remote_name, mr_id))
+    } else if matches.is_present("CHECK") && from_stdin {
+        let result = run_stdin_batch(remote_name, "check", Some("Check"), assume_yes, |mr_id| {
+            check_mr(remote_name, fetch_remote, mr_id)
+        });
+        ("batch-check", None, result)
+    } else if matches.is_present("CHECK") {
+        with_request_id("check", &|mr_id| check_mr(remote_name, fetch_remote, mr_id))
+    } else if matches.is_present("PICK") && from_stdin {
+        let result = run_stdin_batch(remote_name, "pick", Some("Pick"), assume_yes, |mr_id| {
+            pick_mr(remote_name, fetch_remote, mr_id, force, partial_fetch)
+        });
+        ("batch-pick", None, result)
+    } else if matches.is_present("PICK") {
+        with_request_id("pick", &|mr_id| {
+            pick_mr(remote_name, fetch_remote, mr_id, force, partial_fetch)
+        })
+    } else if matches.is_present("TARGET") && from_stdin {
+        let result = run_stdin_batch(
+            remote_name,
+            "checkout-target",
+            Some("Check out the targets of"),
+            assume_yes,
+            |mr_id| checkout_target(remote_name, fetch_remote, mr_id, partial_fetch),
+        );
+        ("batch-checkout-target", None, result)
+    } else if matches.is_present("TARGET") {
+        with_request_id("checkout-target", &|mr_id| {
+            checkout_target(remote_name, fetch_remote, mr_id, partial_fetch)
+        })
+    } else if matches.is_present("WATCH") {
+        let interval = matches
+            .value_of("INTERVAL")
+            .map(String::from)
+            .or_else(|| git::get_config("watchinterval"));
+        with_request_id("watch", &|mr_id| watch_mr(remote_name, mr_id, interval.as_deref()))
+    } else if matches.is_present("REVIEW") {
+        with_request_id("review", &|mr_id| {
+            review_mr(remote_name, fetch_remote, mr_id, branch_name, force, partial_fetch)
+        })
+    } else if from_stdin {
+        let result = run_stdin_batch(
+            remote_name,
+            "checkout",
+            Some("Check out"),
+            assume_yes,
+            |mr_id| checkout_mr(remote_name, fetch_remote, mr_id, None, false, partial_fetch),
+        );
+        ("batch-checkout", None, result)
     } else {
-        checkout_mr(matches.value_of("REQUEST_ID").unwrap().parse().unwrap());
+        with_request_id("checkout", &|mr_id| {
+            checkout_mr(remote_name, fetch_remote, mr_id, branch_name, force, partial_fetch)
+        })
+    };
+    audit::record(operation, remote_name, mr_id, &result);
+    if let Err(error) = result {
+        eprintln!("{}", error);
+        process::exit(1);
     }
 }
@@ -1,8 +1,12 @@
-use crate::remotes::{MergeRequest, Remote};
+use crate::remotes::retry::{diff_client, send_with_retry};
+use crate::remotes::{
+    get_project_path, is_draft_title, map_concurrently, read_json_stream, read_raw_response,
+    ListOptions, MergeRequest, Remote, RequestStatus, Terminology, PULL_REQUEST,
+};
 use log::{debug, trace};
-use regex::Regex;
 use reqwest;
 use serde_derive::{Deserialize, Serialize};
+use serde_json::Value;
 
 #[derive(Debug)]
 pub struct Bitbucket {
@@ -12,14 +16,110 @@ pub struct Bitbucket {
     pub origin: String,
     pub api_root: String,
     pub api_key: String,
+    pub auth_scheme: AuthScheme,
+    pub username: String,
+}
+
+/// How the API key is presented to Bitbucket
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AuthScheme {
+    /// The API key is an app password, sent with the username via basic auth
+    Basic,
+    /// The API key is an OAuth 2.0 access token, sent as a bearer token
+    Bearer,
+}
+
+impl AuthScheme {
+    /// Parse the `req.<domain>.authscheme` setting, defaulting to basic auth
+    pub fn from_config(value: Option<String>) -> Result<AuthScheme, String> {
+        match value.as_ref().map(|value| value.trim().to_lowercase()) {
+            None => Ok(AuthScheme::Basic),
+            Some(ref value) if value == "basic" => Ok(AuthScheme::Basic),
+            Some(ref value) if value == "bearer" => Ok(AuthScheme::Bearer),
+            Some(value) => Err(format!(
+                "Unknown Bitbucket auth scheme {:?}; expected basic or bearer",
+                value
+            )),
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 struct BitbucketPullRequest {
     id: i64,
     title: String,
-    summary: Option<String>,
-    html_url: String,
+    summary: Option<BitbucketContent>,
+    links: BitbucketLinks,
+    #[serde(default)]
+    draft: bool,
+    author: Option<BitbucketUser>,
+    source: BitbucketEndpoint,
+    destination: BitbucketEndpoint,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct BitbucketPage {
+    values: Vec<BitbucketPullRequest>,
+    next: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct BitbucketDiffstatPage {
+    values: Vec<BitbucketDiffstat>,
+    next: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct BitbucketDiffstat {
+    old: Option<BitbucketFile>,
+    new: Option<BitbucketFile>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct BitbucketFile {
+    path: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct BitbucketContent {
+    raw: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct BitbucketUser {
+    nickname: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct BitbucketLinks {
+    html: BitbucketLink,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct BitbucketLink {
+    href: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct BitbucketEndpoint {
+    branch: BitbucketBranch,
+    commit: Option<BitbucketCommit>,
+    repository: Option<BitbucketRepository>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct BitbucketCommit {
+    hash: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct BitbucketRepository {
+    full_name: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct BitbucketBranch {
+    name: String,
 }
 
 impl Remote for Bitbucket {
@@ -35,6 +135,10 @@ impl Remote for Bitbucket {
         false
     }
 
+    fn terminology(&self) -> Terminology {
+        PULL_REQUEST
+    }
+
     fn get_local_req_branch(&mut self, mr_id: i64) -> Result<String, &str> {
         Ok(format!("pr/{mr_id}", mr_id = mr_id))
     }
@@ -43,56 +147,415 @@ impl Remote for Bitbucket {
         Ok(format!("pull/{mr_id}/head", mr_id = mr_id))
     }
 
-    fn get_req_names(&mut self) -> Result<Vec<MergeRequest>, &str> {
-        retrieve_bitbucket_project_pull_requests(self)
+    fn get_req(&mut self, mr_id: i64) -> Result<MergeRequest, &str> {
+        retrieve_bitbucket_pull_request(self, mr_id)
+    }
+
+    fn get_req_raw(&mut self, mr_id: i64) -> Result<Value, String> {
+        let resp = query_bitbucket_api(self, bitbucket_pull_request_url(self, mr_id))?;
+        read_raw_response(resp, &[&self.api_key])
+    }
+
+    fn request_review(&mut self, _mr_id: i64, _usernames: &[String]) -> Result<(), String> {
+        Err(String::from("Requesting reviews isn't supported for Bitbucket"))
+    }
+
+    fn get_req_status(&mut self, _mr_id: i64) -> Result<RequestStatus, String> {
+        Err(String::from(
+            "Checking merge requirements isn't supported for Bitbucket",
+        ))
+    }
+
+    fn get_pipeline_url(&mut self, _mr_id: i64) -> Result<String, String> {
+        Err(String::from("Opening pipelines isn't supported for Bitbucket"))
+    }
+
+    fn get_req_names(&mut self, opts: &ListOptions) -> Result<Vec<MergeRequest>, &str> {
+        retrieve_bitbucket_project_pull_requests(self, opts)
+    }
+
+    fn get_reqs_for_commit(&mut self, _sha: &str) -> Result<Vec<MergeRequest>, String> {
+        Err(String::from(
+            "Finding pull requests by commit isn't supported for Bitbucket",
+        ))
+    }
+
+    fn get_behind_counts(
+        &mut self,
+        _mrs: &[MergeRequest],
+    ) -> Result<Vec<Result<u64, String>>, String> {
+        Err(String::from(
+            "Counting commits behind the target isn't supported for Bitbucket",
+        ))
+    }
+
+    fn get_changed_files(
+        &mut self,
+        mrs: &[MergeRequest],
+    ) -> Result<Vec<Result<Vec<String>, String>>, String> {
+        let remote = &*self;
+        Ok(map_concurrently(mrs, |mr| {
+            retrieve_bitbucket_changed_files(remote, mr.id)
+        }))
+    }
+
+    fn get_req_names_raw(&mut self, opts: &ListOptions) -> Result<Value, String> {
+        let resp = query_bitbucket_api(self, bitbucket_list_url(self, opts))?;
+        read_raw_response(resp, &[&self.api_key])
+    }
+}
+
+/// Add the remote's credentials to a request
+fn authorize(remote: &Bitbucket, request: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+    match remote.auth_scheme {
+        AuthScheme::Basic => request.basic_auth(&remote.username, Some(&remote.api_key)),
+        AuthScheme::Bearer => request.bearer_auth(&remote.api_key),
     }
 }
 
-fn query_bitbucket_api(url: reqwest::Url, token: String) -> reqwest::Response {
-    let client = reqwest::Client::new();
-    client
-        .get(url)
-        .basic_auth("shorsher", Some("RhXcSmcPDdJaAQRDVCCb"))
-        .send()
-        .expect("failed to send request")
+fn query_bitbucket_api(
+    remote: &Bitbucket,
+    url: reqwest::Url,
+) -> Result<reqwest::Response, String> {
+    query_bitbucket_api_with(&reqwest::Client::new(), remote, url)
+}
+
+/// Query one of the Bitbucket API's diff endpoints, which get longer to respond
+fn query_bitbucket_diff_api(
+    remote: &Bitbucket,
+    url: reqwest::Url,
+) -> Result<reqwest::Response, String> {
+    query_bitbucket_api_with(&diff_client(&remote.domain), remote, url)
+}
+
+fn query_bitbucket_api_with(
+    client: &reqwest::Client,
+    remote: &Bitbucket,
+    url: reqwest::Url,
+) -> Result<reqwest::Response, String> {
+    send_with_retry(&remote.domain, || {
+        authorize(remote, client.get(url.clone()))
+    })
 }
 
 fn bitbucket_to_mr(req: BitbucketPullRequest) -> MergeRequest {
+    let draft = req.draft || is_draft_title(&req.title);
+    let source_project = match (req.source.repository, &req.destination.repository) {
+        (Some(source), Some(dest)) if source.full_name != dest.full_name => Some(source.full_name),
+        _ => None,
+    };
     MergeRequest {
         id: req.id,
         title: req.title,
-        description: req.summary,
+        description: req.summary.map(|summary| summary.raw).filter(|raw| !raw.is_empty()),
         source_branch: format!("pullrequests/{}", req.id),
+        head_branch: req.source.branch.name,
+        target_branch: req.destination.branch.name,
+        source_project,
+        head_sha: req.source.commit.map(|commit| commit.hash),
+        url: req.links.html.href,
+        draft,
+        author: req.author.and_then(|author| author.nickname),
+        // Bitbucket pull requests don't have labels or assignees
+        labels: vec![],
+        assignees: vec![],
     }
 }
 
+fn bitbucket_list_url(remote: &Bitbucket, opts: &ListOptions) -> reqwest::Url {
+    let mut url =
+        reqwest::Url::parse(&format!("{}/{}/pullrequests", remote.api_root, remote.id)).unwrap();
+    opts.apply_params(&mut url);
+    url
+}
+
+fn bitbucket_pull_request_url(remote: &Bitbucket, mr_id: i64) -> reqwest::Url {
+    reqwest::Url::parse(&format!(
+        "{}/{}/pullrequests/{}",
+        remote.api_root, remote.id, mr_id
+    ))
+    .unwrap()
+}
+
 fn retrieve_bitbucket_project_pull_requests(
-    remote: &Bitbucket
+    remote: &Bitbucket,
+    opts: &ListOptions,
 ) -> Result<Vec<MergeRequest>, &'static str> {
     trace!("Querying for Bitbucket PR for {:?}", remote);
-    let url = reqwest::Url::parse(&format!("{}/{}/pullrequests", remote.api_root, remote.id)).unwrap();
-    let mut resp = query_bitbucket_api(url, remote.api_root.to_string());
-    debug!("PR list query response: {:?}", resp);
-    let buf: Vec<BitbucketPullRequest> = match resp.json() {
+    collect_pages(bitbucket_list_url(remote, opts), |url| {
+        let mut resp =
+            query_bitbucket_api(remote, url.clone()).map_err(|_| "couldn't reach the API")?;
+        debug!("PR list query response: {:?}", resp);
+        resp.text().map_err(|_| "failed to read API response")
+    })
+}
+
+/// Read every page of a listing from the first page's URL, getting each page's body from
+/// `fetch`. Listings are paginated, with each page linking to the next.
+fn collect_pages<F>(
+    mut url: reqwest::Url,
+    mut fetch: F,
+) -> Result<Vec<MergeRequest>, &'static str>
+where
+    F: FnMut(&reqwest::Url) -> Result<String, &'static str>,
+{
+    let mut mrs = vec![];
+    loop {
+        let page: BitbucketPage = match serde_json::from_str(&fetch(&url)?) {
+            Ok(page) => page,
+            Err(_) => {
+                return Err("failed to read API response");
+            }
+        };
+        mrs.extend(page.values.into_iter().map(bitbucket_to_mr));
+        url = match next_page_url(&url, page.next)? {
+            Some(next) => next,
+            None => return Ok(mrs),
+        };
+    }
+}
+
+/// Get the paths the PR changes, following the pages of its diffstat
+fn retrieve_bitbucket_changed_files(
+    remote: &Bitbucket,
+    mr_id: i64,
+) -> Result<Vec<String>, String> {
+    let mut url = reqwest::Url::parse(&format!(
+        "{}/diffstat",
+        bitbucket_pull_request_url(remote, mr_id)
+    ))
+    .unwrap();
+    let mut paths = vec![];
+    loop {
+        let resp = query_bitbucket_diff_api(remote, url.clone())?;
+        debug!("PR diffstat query response: {:?}", resp);
+        if !resp.status().is_success() {
+            return Err(format!("the API responded with {}", resp.status()));
+        }
+        let page: BitbucketDiffstatPage = read_json_stream(resp)?;
+        paths.extend(bitbucket_changed_paths(page.values));
+        url = match next_page_url(&url, page.next)? {
+            Some(next) => next,
+            None => return Ok(paths),
+        };
+    }
+}
+
+fn bitbucket_changed_paths(diffstats: Vec<BitbucketDiffstat>) -> Vec<String> {
+    let mut paths = vec![];
+    for diffstat in diffstats {
+        let old = diffstat.old.map(|file| file.path);
+        let new = diffstat.new.map(|file| file.path);
+        if old != new {
+            paths.extend(old);
+        }
+        paths.extend(new);
+    }
+    paths
+}
+
+/// Get the next page of a listing, refusing to send credentials to any other origin
+fn next_page_url(
+    current: &reqwest::Url,
+    next: Option<String>,
+) -> Result<Option<reqwest::Url>, &'static str> {
+    let next = match next {
+        Some(next) => next,
+        None => return Ok(None),
+    };
+    match reqwest::Url::parse(&next) {
+        Ok(next) if next.origin() == current.origin() => Ok(Some(next)),
+        _ => Err("the API returned an invalid next page link"),
+    }
+}
+
+fn retrieve_bitbucket_pull_request(
+    remote: &Bitbucket,
+    mr_id: i64,
+) -> Result<MergeRequest, &'static str> {
+    trace!("Querying for Bitbucket PR {} for {:?}", mr_id, remote);
+    let url = bitbucket_pull_request_url(remote, mr_id);
+    let mut resp = query_bitbucket_api(remote, url).map_err(|_| "couldn't reach the API")?;
+    debug!("PR query response: {:?}", resp);
+    let buf: BitbucketPullRequest = match resp.json() {
         Ok(buf) => buf,
         Err(_) => {
             return Err("failed to read API response");
         }
     };
-    Ok(buf.into_iter().map(bitbucket_to_mr).collect())
+    Ok(bitbucket_to_mr(buf))
 }
 
 pub fn get_bitbucket_project_name(origin: &str) -> String {
     trace!("Getting project name for: {}", origin);
-    let project_regex = Regex::new(r".*:(.*/\S+)\.git\w*$").unwrap();
-    let captures = project_regex.captures(origin).unwrap();
-    String::from(&captures[1])
+    get_project_path(origin).unwrap()
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn test_remote(auth_scheme: AuthScheme) -> Bitbucket {
+        Bitbucket {
+            id: String::from("shorsher/test"),
+            domain: String::from("bitbucket.org"),
+            name: String::from("shorsher/test"),
+            origin: String::from("git@bitbucket.org:shorsher/test.git"),
+            api_root: String::from("https://api.bitbucket.org/2.0/repositories"),
+            api_key: String::from("s3cr3t"),
+            auth_scheme,
+            username: String::from("shorsher"),
+        }
+    }
+
+    fn authorization_header(remote: &Bitbucket) -> String {
+        let request = authorize(remote, reqwest::Client::new().get(&remote.api_root[..]))
+            .build()
+            .unwrap();
+        request.headers()["Authorization"]
+            .to_str()
+            .unwrap()
+            .to_string()
+    }
+
+    #[test]
+    fn test_authorize_bearer() {
+        let header = authorization_header(&test_remote(AuthScheme::Bearer));
+        assert_eq!("Bearer s3cr3t", header);
+    }
+
+    #[test]
+    fn test_authorize_basic() {
+        let header = authorization_header(&test_remote(AuthScheme::Basic));
+        assert_eq!("Basic c2hvcnNoZXI6czNjcjN0", header);
+    }
+
+    #[test]
+    fn test_auth_scheme_from_config() {
+        assert_eq!(Ok(AuthScheme::Basic), AuthScheme::from_config(None));
+        assert_eq!(
+            Ok(AuthScheme::Bearer),
+            AuthScheme::from_config(Some(String::from("Bearer")))
+        );
+        assert!(AuthScheme::from_config(Some(String::from("oauth"))).is_err());
+    }
+
+    #[test]
+    fn test_bitbucket_page_fixture() {
+        let page: BitbucketPage =
+            serde_json::from_str(include_str!("../../tests/fixtures/bitbucket/pullrequests.json"))
+                .unwrap();
+        let mrs: Vec<MergeRequest> = page.values.into_iter().map(bitbucket_to_mr).collect();
+        assert_eq!(1, mrs.len());
+        assert_eq!(7, mrs[0].id);
+        assert_eq!("pullrequests/7", mrs[0].source_branch);
+        assert_eq!("fix-login", mrs[0].head_branch);
+        assert_eq!("master", mrs[0].target_branch);
+        assert_eq!(Some(String::from("alice/test")), mrs[0].source_project);
+        assert_eq!(Some(String::from("9a8b7c6d5e4f")), mrs[0].head_sha);
+        assert_eq!(Some(String::from("Fixes #40")), mrs[0].description);
+        assert_eq!("https://bitbucket.org/shorsher/test/pull-requests/7", mrs[0].url);
+        assert!(mrs[0].draft);
+        assert_eq!(Some(String::from("alice")), mrs[0].author);
+        assert!(page.next.is_some());
+    }
+
+    #[test]
+    fn test_next_page_url() {
+        let current = bitbucket_list_url(&test_remote(AuthScheme::Basic), &Default::default());
+        assert_eq!(Ok(None), next_page_url(&current, None));
+        let next = format!("{}?page=2", current);
+        let next_url = next_page_url(&current, Some(next.clone())).unwrap().unwrap();
+        assert_eq!(next, next_url.as_str());
+        let elsewhere = String::from("https://example.com/pullrequests?page=2");
+        assert!(next_page_url(&current, Some(elsewhere)).is_err());
+        // The same host over plain HTTP or on another port is another origin
+        let insecure = next.replacen("https://", "http://", 1);
+        assert!(next_page_url(&current, Some(insecure)).is_err());
+        let other_port = next.replacen("api.bitbucket.org", "api.bitbucket.org:8443", 1);
+        assert!(next_page_url(&current, Some(other_port)).is_err());
+    }
+
+    /// Serve recorded response bodies by URL, as `collect_pages` fetches them
+    fn replay<'a>(
+        recordings: &'a [(&str, &str)],
+    ) -> impl FnMut(&reqwest::Url) -> Result<String, &'static str> + 'a {
+        move |url| {
+            let (_, body) = recordings
+                .iter()
+                .find(|(recorded, _)| *recorded == url.as_str())
+                .unwrap_or_else(|| panic!("nothing was recorded for {}", url));
+            Ok(body.to_string())
+        }
+    }
+
+    #[test]
+    fn test_bitbucket_list_replays_pages() {
+        let first = bitbucket_list_url(&test_remote(AuthScheme::Bearer), &Default::default());
+        let recordings = [
+            (
+                first.as_str(),
+                include_str!("../../tests/fixtures/bitbucket/pullrequests.json"),
+            ),
+            (
+                "https://api.bitbucket.org/2.0/repositories/shorsher/test/pullrequests?page=2",
+                include_str!("../../tests/fixtures/bitbucket/pullrequests_page2.json"),
+            ),
+        ];
+        let mrs = collect_pages(first.clone(), replay(&recordings)).unwrap();
+        assert_eq!(vec![7, 8], mrs.iter().map(|mr| mr.id).collect::<Vec<_>>());
+        assert_eq!(None, mrs[1].description);
+    }
+
+    #[test]
+    fn test_bitbucket_list_replay_refuses_foreign_next_page() {
+        let first = bitbucket_list_url(&test_remote(AuthScheme::Bearer), &Default::default());
+        let page = include_str!("../../tests/fixtures/bitbucket/pullrequests.json")
+            .replace("https://api.bitbucket.org", "http://api.bitbucket.org");
+        let recordings = [(first.as_str(), page.as_str())];
+        let result = collect_pages(first.clone(), replay(&recordings));
+        assert_eq!(Some("the API returned an invalid next page link"), result.err());
+    }
+
+    #[test]
+    fn test_bitbucket_list_replays_error() {
+        let first = bitbucket_list_url(&test_remote(AuthScheme::Bearer), &Default::default());
+        let error = include_str!("../../tests/fixtures/bitbucket/error.json");
+        let recordings = [(first.as_str(), error)];
+        let result = collect_pages(first.clone(), replay(&recordings));
+        assert_eq!(Some("failed to read API response"), result.err());
+    }
+
+    #[test]
+    fn test_bitbucket_changed_paths() {
+        let page: BitbucketDiffstatPage = serde_json::from_str(
+            r#"{"values": [
+                {"old": {"path": "services/foo/lib.rs"}, "new": {"path": "services/foo/lib.rs"}},
+                {"old": null, "new": {"path": "services/foo/new.rs"}},
+                {"old": {"path": "docs/gone.md"}, "new": null},
+                {"old": {"path": "docs/old.md"}, "new": {"path": "docs/new.md"}}
+            ]}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            vec![
+                "services/foo/lib.rs",
+                "services/foo/new.rs",
+                "docs/gone.md",
+                "docs/old.md",
+                "docs/new.md"
+            ],
+            bitbucket_changed_paths(page.values)
+        );
+    }
+
+    #[test]
+    fn test_bitbucket_error_fixture() {
+        let error = include_str!("../../tests/fixtures/bitbucket/error.json");
+        assert!(serde_json::from_str::<BitbucketPage>(error).is_err());
+        assert!(serde_json::from_str::<BitbucketPullRequest>(error).is_err());
+    }
+
     #[test]
     fn test_get_bitbucket_project_name() {
         let name = get_bitbucket_project_name("git@bitbucket.org:shorsher/test.git");
@@ -1,8 +1,13 @@
-use crate::remotes::{MergeRequest, Remote};
+use crate::remotes::github_app::AppAuth;
+use crate::remotes::retry::{diff_client, send_with_retry};
+use crate::remotes::{
+    get_project_path, map_concurrently, normalize_username, read_json_stream, read_raw_response,
+    ListOptions, MergeRequest, Remote, RequestStatus, Terminology, PULL_REQUEST,
+};
 use log::{debug, trace};
-use regex::Regex;
-use reqwest;
+use reqwest::{self, StatusCode};
 use serde_derive::{Deserialize, Serialize};
+use serde_json::Value;
 
 #[derive(Debug)]
 pub struct GitHub {
@@ -12,6 +17,8 @@ pub struct GitHub {
     pub origin: String,
     pub api_root: String,
     pub api_key: String,
+    /// Set when authenticating as a GitHub App installation instead of with `api_key`
+    pub app: Option<AppAuth>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -21,6 +28,95 @@ struct GitHubPullRequest {
     title: String,
     body: Option<String>,
     html_url: String,
+    #[serde(default)]
+    draft: bool,
+    user: Option<GitHubUser>,
+    #[serde(default)]
+    labels: Vec<GitHubLabel>,
+    #[serde(default)]
+    assignees: Vec<GitHubUser>,
+    state: Option<String>,
+    head: GitHubBranch,
+    base: GitHubBranch,
+}
+
+/// The results of an issue search, which includes pull requests
+#[derive(Serialize, Deserialize, Debug)]
+struct GitHubSearchResults {
+    items: Vec<GitHubSearchItem>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct GitHubSearchItem {
+    number: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct GitHubLabel {
+    name: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct GitHubBranch {
+    #[serde(rename = "ref")]
+    ref_name: String,
+    sha: String,
+    repo: Option<GitHubRepo>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct GitHubRepo {
+    full_name: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct GitHubMergeability {
+    mergeable: Option<bool>,
+    head: GitHubBranch,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct GitHubReview {
+    user: Option<GitHubUser>,
+    state: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct GitHubUser {
+    login: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct GitHubCombinedStatus {
+    state: String,
+    total_count: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct GitHubCheckRuns {
+    check_runs: Vec<GitHubCheckRun>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct GitHubCheckRun {
+    status: String,
+    conclusion: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct GitHubFile {
+    filename: String,
+    previous_filename: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct GitHubComparison {
+    behind_by: u64,
+}
+
+#[derive(Serialize, Debug)]
+struct GitHubReviewRequest<'a> {
+    reviewers: Vec<&'a str>,
 }
 
 impl Remote for GitHub {
@@ -40,42 +136,173 @@ impl Remote for GitHub {
         Ok(format!("pull/{mr_id}/head", mr_id = mr_id))
     }
 
-    fn get_req_names(&mut self) -> Result<Vec<MergeRequest>, &str> {
-        retrieve_github_project_pull_requests(self)
+    fn get_req(&mut self, mr_id: i64) -> Result<MergeRequest, &str> {
+        retrieve_github_pull_request(self, mr_id)
+    }
+
+    fn get_req_raw(&mut self, mr_id: i64) -> Result<Value, String> {
+        let resp = query_github_api(self, github_pull_request_url(self, mr_id))?;
+        read_raw_response(resp, &[&api_token(self)])
+    }
+
+    fn request_review(&mut self, mr_id: i64, usernames: &[String]) -> Result<(), String> {
+        request_github_reviewers(self, mr_id, usernames)
+    }
+
+    fn get_req_status(&mut self, mr_id: i64) -> Result<RequestStatus, String> {
+        retrieve_github_status(self, mr_id)
+    }
+
+    fn get_pipeline_url(&mut self, mr_id: i64) -> Result<String, String> {
+        retrieve_github_checks_url(self, mr_id)
+    }
+
+    fn get_req_names(&mut self, opts: &ListOptions) -> Result<Vec<MergeRequest>, &str> {
+        retrieve_github_project_pull_requests(self, opts)
+    }
+
+    fn get_req_names_raw(&mut self, opts: &ListOptions) -> Result<Value, String> {
+        let url = match opts.assignee {
+            Some(ref assignee) => github_assignee_search_url(self, assignee),
+            None => github_list_url(self, opts),
+        };
+        let resp = query_github_api(self, url)?;
+        read_raw_response(resp, &[&api_token(self)])
+    }
+
+    fn get_reqs_for_commit(&mut self, sha: &str) -> Result<Vec<MergeRequest>, String> {
+        let url = format!("{}/{}/commits/{}/pulls", self.api_root, self.id, sha);
+        let pulls: Vec<GitHubPullRequest> = query_github_json(self, &url)?;
+        Ok(github_open_pulls(pulls))
+    }
+
+    fn get_behind_counts(
+        &mut self,
+        mrs: &[MergeRequest],
+    ) -> Result<Vec<Result<u64, String>>, String> {
+        let remote = &*self;
+        Ok(map_concurrently(mrs, |mr| {
+            count_github_commits_behind(remote, mr)
+        }))
+    }
+
+    fn get_changed_files(
+        &mut self,
+        mrs: &[MergeRequest],
+    ) -> Result<Vec<Result<Vec<String>, String>>, String> {
+        let remote = &*self;
+        Ok(map_concurrently(mrs, |mr| {
+            retrieve_github_changed_files(remote, mr.id)
+        }))
     }
 
     fn has_useful_branch_names(&mut self) -> bool {
         false
     }
+
+    fn terminology(&self) -> Terminology {
+        PULL_REQUEST
+    }
 }
 
 /// Convert a GitHub PR to a git-req MergeRequest
 fn github_to_mr(req: GitHubPullRequest) -> MergeRequest {
+    let source_project = match (req.head.repo, &req.base.repo) {
+        (Some(head), Some(base)) if head.full_name != base.full_name => Some(head.full_name),
+        _ => None,
+    };
     MergeRequest {
         id: req.number,
         title: req.title,
         description: req.body,
         source_branch: format!("pr/{}", req.number),
+        head_branch: req.head.ref_name,
+        target_branch: req.base.ref_name,
+        source_project,
+        head_sha: Some(req.head.sha),
+        url: req.html_url,
+        draft: req.draft,
+        author: req.user.map(|user| user.login),
+        labels: req.labels.into_iter().map(|label| label.name).collect(),
+        assignees: req.assignees.into_iter().map(|user| user.login).collect(),
+    }
+}
+
+/// Get the token to authenticate API requests with
+fn api_token(remote: &GitHub) -> String {
+    match remote.app {
+        Some(ref app) => app.token(),
+        None => remote.api_key.clone(),
     }
 }
 
 /// Query the GitHub API
-fn query_github_api(url: reqwest::Url, token: String) -> reqwest::Response {
-    let client = reqwest::Client::new();
-    client
-        .get(url)
-        .header("Authorization", format!("token {}", token))
-        .send()
-        .expect("failed to send request")
+fn query_github_api(remote: &GitHub, url: reqwest::Url) -> Result<reqwest::Response, String> {
+    query_github_api_with(&reqwest::Client::new(), remote, url)
+}
+
+/// Query one of the GitHub API's diff endpoints, which get longer to respond
+fn query_github_diff_api(remote: &GitHub, url: reqwest::Url) -> Result<reqwest::Response, String> {
+    query_github_api_with(&diff_client(&remote.domain), remote, url)
+}
+
+fn query_github_api_with(
+    client: &reqwest::Client,
+    remote: &GitHub,
+    url: reqwest::Url,
+) -> Result<reqwest::Response, String> {
+    send_with_retry(&remote.domain, || {
+        client
+            .get(url.clone())
+            .header("Authorization", format!("token {}", api_token(remote)))
+    })
+}
+
+/// Build the URL listing the pull requests for the current project
+fn github_list_url(remote: &GitHub, opts: &ListOptions) -> reqwest::Url {
+    let mut url = reqwest::Url::parse(&format!("{}/{}/pulls", remote.api_root, remote.id)).unwrap();
+    opts.apply_params(&mut url);
+    url
+}
+
+/// Build the URL searching the current project's open pull requests assigned to a user. The
+/// pull request listing can't filter by assignee, and only its first page is read.
+fn github_assignee_search_url(remote: &GitHub, assignee: &str) -> reqwest::Url {
+    let api_base = remote.api_root.trim_end_matches("/repos");
+    let query = format!(
+        "repo:{} is:pr is:open assignee:{}",
+        remote.id,
+        normalize_username(assignee)
+    );
+    let mut url = reqwest::Url::parse(&format!("{}/search/issues", api_base)).unwrap();
+    url.query_pairs_mut()
+        .append_pair("q", &query)
+        .append_pair("sort", "created")
+        .append_pair("order", "desc")
+        .append_pair("per_page", "100");
+    url
+}
+
+/// Build the URL of a single pull request for the current project
+fn github_pull_request_url(remote: &GitHub, mr_id: i64) -> reqwest::Url {
+    reqwest::Url::parse(&format!(
+        "{}/{}/pulls/{}",
+        remote.api_root, remote.id, mr_id
+    ))
+    .unwrap()
 }
 
 /// Get the pull requests for the current project
 fn retrieve_github_project_pull_requests(
     remote: &GitHub,
+    opts: &ListOptions,
 ) -> Result<Vec<MergeRequest>, &'static str> {
     trace!("Querying for GitHub PR for {:?}", remote);
-    let url = reqwest::Url::parse(&format!("{}/{}/pulls", remote.api_root, remote.id)).unwrap();
-    let mut resp = query_github_api(url, remote.api_key.to_string());
+    if let Some(ref assignee) = opts.assignee {
+        return retrieve_github_assigned_pull_requests(remote, assignee);
+    }
+    let url = github_list_url(remote, opts);
+    let mut resp = query_github_api(remote, url).map_err(|_| "couldn't reach the API")?;
     debug!("PR list query response: {:?}", resp);
     let buf: Vec<GitHubPullRequest> = match resp.json() {
         Ok(buf) => buf,
@@ -86,21 +313,606 @@ fn retrieve_github_project_pull_requests(
     Ok(buf.into_iter().map(github_to_mr).collect())
 }
 
+/// Get the current project's open pull requests assigned to a user
+fn retrieve_github_assigned_pull_requests(
+    remote: &GitHub,
+    assignee: &str,
+) -> Result<Vec<MergeRequest>, &'static str> {
+    let url = github_assignee_search_url(remote, assignee);
+    let mut resp = query_github_api(remote, url).map_err(|_| "couldn't reach the API")?;
+    debug!("PR search response: {:?}", resp);
+    let results: GitHubSearchResults = match resp.json() {
+        Ok(results) => results,
+        Err(_) => {
+            return Err("failed to read API response");
+        }
+    };
+    // Search results are issues, without the branches, so get each pull request itself
+    map_concurrently(&results.items, |item| {
+        retrieve_github_pull_request(remote, item.number)
+    })
+    .into_iter()
+    .collect()
+}
+
+/// Convert the pull requests that are still open; a commit's pull requests include closed ones
+fn github_open_pulls(pulls: Vec<GitHubPullRequest>) -> Vec<MergeRequest> {
+    pulls
+        .into_iter()
+        .filter(|pull| matches!(pull.state.as_deref(), None | Some("open")))
+        .map(github_to_mr)
+        .collect()
+}
+
+/// Get a single pull request for the current project
+fn retrieve_github_pull_request(remote: &GitHub, mr_id: i64) -> Result<MergeRequest, &'static str> {
+    trace!("Querying for GitHub PR {} for {:?}", mr_id, remote);
+    let url = github_pull_request_url(remote, mr_id);
+    let mut resp = query_github_api(remote, url).map_err(|_| "couldn't reach the API")?;
+    debug!("PR query response: {:?}", resp);
+    let buf: GitHubPullRequest = match resp.json() {
+        Ok(buf) => buf,
+        Err(_) => {
+            return Err("failed to read API response");
+        }
+    };
+    Ok(github_to_mr(buf))
+}
+
+/// Query the GitHub API, reading the response as JSON
+fn query_github_json<T>(remote: &GitHub, url: &str) -> Result<T, String>
+where
+    T: serde::de::DeserializeOwned,
+{
+    let url = reqwest::Url::parse(url).map_err(|error| error.to_string())?;
+    let mut resp = query_github_api(remote, url)?;
+    debug!("Query response: {:?}", resp);
+    if !resp.status().is_success() {
+        return Err(format!("the API responded with {}", resp.status()));
+    }
+    resp.json()
+        .map_err(|_| String::from("failed to read API response"))
+}
+
+/// Check whether a PR is approved from its reviews: each reviewer's latest review counts, and
+/// any outstanding request for changes blocks approval
+fn github_reviews_approved(reviews: &[GitHubReview]) -> bool {
+    let mut latest: Vec<(&str, &str)> = vec![];
+    for review in reviews {
+        let login = match review.user {
+            Some(ref user) => user.login.as_str(),
+            None => continue,
+        };
+        // Comments don't change a reviewer's verdict
+        if review.state == "COMMENTED" || review.state == "PENDING" {
+            continue;
+        }
+        latest.retain(|(reviewer, _)| *reviewer != login);
+        latest.push((login, &review.state));
+    }
+    latest.iter().any(|(_, state)| *state == "APPROVED")
+        && !latest
+            .iter()
+            .any(|(_, state)| *state == "CHANGES_REQUESTED")
+}
+
+/// Check whether the statuses and check runs for a commit passed. A commit without any counts
+/// as not passing.
+fn github_checks_green(status: &GitHubCombinedStatus, checks: &GitHubCheckRuns) -> bool {
+    if status.total_count == 0 && checks.check_runs.is_empty() {
+        return false;
+    }
+    let statuses_green = status.total_count == 0 || status.state == "success";
+    let checks_green = checks.check_runs.iter().all(|check| {
+        check.status == "completed"
+            && matches!(
+                check.conclusion.as_deref(),
+                Some("success") | Some("neutral") | Some("skipped")
+            )
+    });
+    statuses_green && checks_green
+}
+
+/// Get the merge gates of the PR having the given ID
+fn retrieve_github_status(remote: &GitHub, mr_id: i64) -> Result<RequestStatus, String> {
+    let pull_url = github_pull_request_url(remote, mr_id);
+    let pull: GitHubMergeability = query_github_json(remote, pull_url.as_str())?;
+    let reviews: Vec<GitHubReview> =
+        query_github_json(remote, &format!("{}/reviews?per_page=100", pull_url))?;
+    let (status, checks) = retrieve_github_checks(remote, &pull.head.sha)?;
+    Ok(RequestStatus {
+        mergeable: pull.mergeable,
+        approved: Some(github_reviews_approved(&reviews)),
+        pipeline_green: Some(github_checks_green(&status, &checks)),
+        pipeline_running: Some(github_checks_running(&status, &checks)),
+    })
+}
+
+/// Check whether any status or check run reported for a commit is still to finish
+fn github_checks_running(status: &GitHubCombinedStatus, checks: &GitHubCheckRuns) -> bool {
+    (status.total_count > 0 && status.state == "pending")
+        || checks
+            .check_runs
+            .iter()
+            .any(|check| check.status != "completed")
+}
+
+/// Get the statuses and check runs reported for a commit
+fn retrieve_github_checks(
+    remote: &GitHub,
+    sha: &str,
+) -> Result<(GitHubCombinedStatus, GitHubCheckRuns), String> {
+    let commit_url = format!("{}/{}/commits/{}", remote.api_root, remote.id, sha);
+    let status = query_github_json(remote, &format!("{}/status", commit_url))?;
+    let checks = query_github_json(remote, &format!("{}/check-runs?per_page=100", commit_url))?;
+    Ok((status, checks))
+}
+
+/// Get the URL of the PR's checks tab, if anything has reported checks for its head
+fn retrieve_github_checks_url(remote: &GitHub, mr_id: i64) -> Result<String, String> {
+    let pull_url = github_pull_request_url(remote, mr_id);
+    let pull: GitHubPullRequest = query_github_json(remote, pull_url.as_str())?;
+    let (status, checks) = retrieve_github_checks(remote, &pull.head.sha)?;
+    if status.total_count == 0 && checks.check_runs.is_empty() {
+        return Err(String::from("nothing has reported checks for it"));
+    }
+    Ok(format!("{}/checks", pull.html_url))
+}
+
+/// Count the commits on the PR's base branch that its head doesn't have yet
+fn count_github_commits_behind(remote: &GitHub, mr: &MergeRequest) -> Result<u64, String> {
+    let head_sha = mr
+        .head_sha
+        .as_ref()
+        .ok_or_else(|| String::from("GitHub didn't report a head commit"))?;
+    let url = reqwest::Url::parse(&format!(
+        "{}/{}/compare/{}...{}",
+        remote.api_root, remote.id, mr.target_branch, head_sha
+    ))
+    .map_err(|error| error.to_string())?;
+    let mut resp = query_github_api(remote, url)?;
+    debug!("Compare query response: {:?}", resp);
+    if !resp.status().is_success() {
+        return Err(format!("the compare API responded with {}", resp.status()));
+    }
+    let comparison: GitHubComparison = resp
+        .json()
+        .map_err(|_| String::from("failed to read API response"))?;
+    Ok(comparison.behind_by)
+}
+
+/// Get the paths the PR changes, following the pages of its file listing
+fn retrieve_github_changed_files(remote: &GitHub, mr_id: i64) -> Result<Vec<String>, String> {
+    let mut url = reqwest::Url::parse(&format!(
+        "{}/files?per_page=100",
+        github_pull_request_url(remote, mr_id)
+    ))
+    .unwrap();
+    let mut paths = vec![];
+    loop {
+        let resp = query_github_diff_api(remote, url.clone())?;
+        debug!("PR files query response: {:?}", resp);
+        if !resp.status().is_success() {
+            return Err(format!("the API responded with {}", resp.status()));
+        }
+        let link = resp.headers().get("Link").and_then(|link| link.to_str().ok());
+        let next = next_page_url(&url, link)?;
+        // Only the paths are deserialized, so each file's patch is skipped as it arrives
+        let files: Vec<GitHubFile> = read_json_stream(resp)?;
+        for file in files {
+            paths.extend(file.previous_filename);
+            paths.push(file.filename);
+        }
+        url = match next {
+            Some(next) => next,
+            None => return Ok(paths),
+        };
+    }
+}
+
+/// Get the next page from a `Link` header, refusing to send credentials to any other origin
+fn next_page_url(
+    current: &reqwest::Url,
+    link: Option<&str>,
+) -> Result<Option<reqwest::Url>, String> {
+    let next = match link.and_then(next_page_link) {
+        Some(next) => next,
+        None => return Ok(None),
+    };
+    match reqwest::Url::parse(&next) {
+        Ok(next) if next.origin() == current.origin() => Ok(Some(next)),
+        _ => Err(String::from("the API returned an invalid next page link")),
+    }
+}
+
+/// Find the `rel="next"` URL in a `Link` header
+fn next_page_link(link: &str) -> Option<String> {
+    link.split(',').find_map(|part| {
+        let mut sections = part.split(';').map(str::trim);
+        let url = sections.next()?;
+        if sections.any(|section| section == r#"rel="next""#) {
+            Some(
+                url.trim_start_matches('<')
+                    .trim_end_matches('>')
+                    .to_string(),
+            )
+        } else {
+            None
+        }
+    })
+}
+
+/// Request reviews of the PR having the given ID
+fn request_github_reviewers(
+    remote: &GitHub,
+    mr_id: i64,
+    usernames: &[String],
+) -> Result<(), String> {
+    let reviewers: Vec<&str> = usernames
+        .iter()
+        .map(|name| normalize_username(name))
+        .collect();
+    trace!("Requesting reviews from {:?} on PR {}", reviewers, mr_id);
+    let url = reqwest::Url::parse(&format!(
+        "{}/{}/pulls/{}/requested_reviewers",
+        remote.api_root, remote.id, mr_id
+    ))
+    .unwrap();
+    let request = GitHubReviewRequest { reviewers };
+    let client = reqwest::Client::new();
+    let resp = send_with_retry(&remote.domain, || {
+        client
+            .post(url.clone())
+            .header("Authorization", format!("token {}", api_token(remote)))
+            .json(&request)
+    })?;
+    debug!("Review request response: {:?}", resp);
+    match resp.status() {
+        status if status.is_success() => Ok(()),
+        StatusCode::UNPROCESSABLE_ENTITY => Err(String::from(
+            "GitHub rejected the reviewers; check that each user exists and is a collaborator",
+        )),
+        status => Err(format!("GitHub refused the review request ({})", status)),
+    }
+}
+
+/// Extract the `owner/repo` project and, if present, the pull request ID from a GitHub URL.
+/// Handles URLs copied from the browser such as `https://github.com/owner/repo/tree/main` and
+/// `https://github.com/owner/repo/pull/42/files`.
+pub fn parse_github_url(url: &str) -> Option<(String, Option<i64>)> {
+    let url = url.split(['?', '#']).next()?;
+    let path = get_project_path(url)?;
+    let mut segments = path.split('/');
+    let owner = segments.next()?;
+    let repo = segments.next()?.trim_end_matches(".git");
+    let rest: Vec<&str> = segments.collect();
+    let pr_id = match rest.as_slice() {
+        ["pull", id, ..] => id.parse().ok(),
+        _ => None,
+    };
+    Some((format!("{}/{}", owner, repo), pr_id))
+}
+
 /// Extract the project name from a Github origin URL
 pub fn get_github_project_name(origin: &str) -> String {
     trace!("Getting project name for: {}", origin);
-    let project_regex = Regex::new(r".*:(.*/\S+)\.git\w*$").unwrap();
-    let captures = project_regex.captures(origin).unwrap();
-    String::from(&captures[1])
+    parse_github_url(origin).unwrap().0
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn pull_request_json(head_repo: &str) -> String {
+        format!(
+            r#"{{
+                "id": 1, "number": 42, "title": "Fix things", "body": null,
+                "html_url": "https://github.com/my_org/my_project/pull/42",
+                "head": {{"ref": "fix-things", "sha": "abc123", "repo": {}}},
+                "base": {{"ref": "master", "sha": "def456", "repo": {{"full_name": "my_org/my_project"}}}}
+            }}"#,
+            head_repo
+        )
+    }
+
+    #[test]
+    fn test_github_to_mr_fork() {
+        let json = pull_request_json(r#"{"full_name": "alice/my_project"}"#);
+        let mr = github_to_mr(serde_json::from_str(&json).unwrap());
+        assert_eq!(Some(String::from("alice/my_project")), mr.source_project);
+        assert_eq!("master", mr.target_branch);
+        assert_eq!(Some(String::from("abc123")), mr.head_sha);
+    }
+
+    #[test]
+    fn test_github_to_mr_same_repo() {
+        let json = pull_request_json(r#"{"full_name": "my_org/my_project"}"#);
+        let mr = github_to_mr(serde_json::from_str(&json).unwrap());
+        assert_eq!(None, mr.source_project);
+    }
+
+    #[test]
+    fn test_github_to_mr_deleted_fork() {
+        let mr = github_to_mr(serde_json::from_str(&pull_request_json("null")).unwrap());
+        assert_eq!(None, mr.source_project);
+    }
+
+    #[test]
+    fn test_github_pulls_fixture() {
+        let pulls: Vec<GitHubPullRequest> =
+            serde_json::from_str(include_str!("../../tests/fixtures/github/pulls.json")).unwrap();
+        let mrs: Vec<MergeRequest> = pulls.into_iter().map(github_to_mr).collect();
+        assert_eq!(vec![42, 43], mrs.iter().map(|mr| mr.id).collect::<Vec<_>>());
+        assert_eq!(
+            Some(String::from("alice/my_project")),
+            mrs[0].source_project
+        );
+        assert_eq!("pr/42", mrs[0].source_branch);
+        assert_eq!("fix-login", mrs[0].head_branch);
+        assert!(!mrs[0].draft);
+        assert_eq!(None, mrs[1].source_project);
+        assert_eq!("release/2.x", mrs[1].target_branch);
+        assert!(mrs[1].draft);
+        assert_eq!(Some(String::from("jrdev")), mrs[1].author);
+        assert_eq!(vec!["jrdev"], mrs[0].assignees);
+        assert!(mrs[1].assignees.is_empty());
+    }
+
+    #[test]
+    fn test_github_pulls_fixture_label_filters() {
+        let pulls: Vec<GitHubPullRequest> =
+            serde_json::from_str(include_str!("../../tests/fixtures/github/pulls.json")).unwrap();
+        let mrs: Vec<MergeRequest> = pulls.into_iter().map(github_to_mr).collect();
+        let ids = |opts: &ListOptions| -> Vec<i64> {
+            mrs.iter()
+                .filter(|mr| opts.matches_labels(mr))
+                .map(|mr| mr.id)
+                .collect()
+        };
+        let excluding = ListOptions {
+            exclude_labels: vec![String::from("enhancement")],
+            ..Default::default()
+        };
+        assert_eq!(vec![42], ids(&excluding));
+        let including = ListOptions {
+            labels: vec![String::from("Enhancement")],
+            ..Default::default()
+        };
+        assert_eq!(vec![43], ids(&including));
+        let both = ListOptions {
+            labels: vec![String::from("enhancement")],
+            exclude_labels: vec![String::from("enhancement")],
+            ..Default::default()
+        };
+        assert!(ids(&both).is_empty());
+    }
+
+    #[test]
+    fn test_github_pulls_fixture_assignee_filter() {
+        let pulls: Vec<GitHubPullRequest> =
+            serde_json::from_str(include_str!("../../tests/fixtures/github/pulls.json")).unwrap();
+        let assigned = ListOptions {
+            assignee: Some(String::from("JRDev")),
+            ..Default::default()
+        };
+        let assigned: Vec<i64> = pulls
+            .into_iter()
+            .map(github_to_mr)
+            .filter(|mr| assigned.matches_assignee(mr))
+            .map(|mr| mr.id)
+            .collect();
+        assert_eq!(vec![42], assigned);
+    }
+
+    #[test]
+    fn test_github_assignee_search_url() {
+        let remote = |api_root: &str| GitHub {
+            id: String::from("my_org/my_project"),
+            domain: String::from("github.com"),
+            name: String::from("my_org/my_project"),
+            origin: String::from("git@github.com:my_org/my_project.git"),
+            api_root: String::from(api_root),
+            api_key: String::new(),
+            app: None,
+        };
+        let url = github_assignee_search_url(&remote("https://api.github.com/repos"), "@jrdev");
+        assert_eq!(
+            "https://api.github.com/search/issues?\
+             q=repo%3Amy_org%2Fmy_project+is%3Apr+is%3Aopen+assignee%3Ajrdev\
+             &sort=created&order=desc&per_page=100",
+            url.as_str()
+        );
+        let url = github_assignee_search_url(&remote("https://api.ghe.corp/repos"), "jrdev");
+        assert!(url.as_str().starts_with("https://api.ghe.corp/search/issues?"));
+    }
+
+    #[test]
+    fn test_github_search_fixture() {
+        let results: GitHubSearchResults =
+            serde_json::from_str(include_str!("../../tests/fixtures/github/search_issues.json"))
+                .unwrap();
+        assert_eq!(vec![42], results.items.iter().map(|item| item.number).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_github_open_pulls() {
+        let mut pulls: Value =
+            serde_json::from_str(include_str!("../../tests/fixtures/github/pulls.json")).unwrap();
+        pulls[0]["state"] = Value::from("closed");
+        pulls[1]["state"] = Value::from("open");
+        let mrs = github_open_pulls(serde_json::from_value(pulls).unwrap());
+        assert_eq!(vec![43], mrs.iter().map(|mr| mr.id).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_github_pull_fork_fixture() {
+        let json = include_str!("../../tests/fixtures/github/pull_fork.json");
+        let mr = github_to_mr(serde_json::from_str(json).unwrap());
+        assert_eq!(Some(String::from("alice/my_project")), mr.source_project);
+        assert_eq!("https://github.com/my_org/my_project/pull/42", mr.url);
+        assert_eq!(Some(String::from("Fixes #40")), mr.description);
+        let pull: GitHubMergeability = serde_json::from_str(json).unwrap();
+        assert_eq!(Some(true), pull.mergeable);
+        assert_eq!("9a8b7c6d5e4f3a2b1c0d9e8f7a6b5c4d3e2f1a0b", pull.head.sha);
+    }
+
+    #[test]
+    fn test_next_page_link() {
+        let link = concat!(
+            r#"<https://api.github.com/repositories/1/pulls/42/files?per_page=100&page=2>; rel="next", "#,
+            r#"<https://api.github.com/repositories/1/pulls/42/files?per_page=100&page=3>; rel="last""#
+        );
+        assert_eq!(
+            Some(String::from(
+                "https://api.github.com/repositories/1/pulls/42/files?per_page=100&page=2"
+            )),
+            next_page_link(link)
+        );
+        let last_page =
+            r#"<https://api.github.com/repositories/1/pulls/42/files?page=1>; rel="first""#;
+        assert_eq!(None, next_page_link(last_page));
+    }
+
+    #[test]
+    fn test_next_page_url_same_origin() {
+        let current = reqwest::Url::parse("https://api.github.com/repositories/1/pulls/42/files")
+            .unwrap();
+        let link = |url: &str| format!(r#"<{}>; rel="next""#, url);
+        assert_eq!(Ok(None), next_page_url(&current, None));
+        let next = "https://api.github.com/repositories/1/pulls/42/files?page=2";
+        let next_url = next_page_url(&current, Some(&link(next))).unwrap().unwrap();
+        assert_eq!(next, next_url.as_str());
+        for elsewhere in &[
+            "https://example.com/repositories/1/pulls/42/files?page=2",
+            "http://api.github.com/repositories/1/pulls/42/files?page=2",
+            "https://api.github.com:8443/repositories/1/pulls/42/files?page=2",
+        ] {
+            assert!(next_page_url(&current, Some(&link(elsewhere))).is_err());
+        }
+    }
+
+    #[test]
+    fn test_github_not_found_fixture() {
+        let error = include_str!("../../tests/fixtures/github/not_found.json");
+        assert!(serde_json::from_str::<GitHubPullRequest>(error).is_err());
+        assert!(serde_json::from_str::<Vec<GitHubPullRequest>>(error).is_err());
+    }
+
     #[test]
     fn test_get_github_project_name() {
         let name = get_github_project_name("git@github.com:my_org/my_project.git");
         assert_eq!("my_org/my_project", name);
     }
+
+    fn reviews(reviews: &[(&str, &str)]) -> Vec<GitHubReview> {
+        reviews
+            .iter()
+            .map(|(login, state)| GitHubReview {
+                user: Some(GitHubUser {
+                    login: String::from(*login),
+                }),
+                state: String::from(*state),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_github_reviews_approved() {
+        assert!(github_reviews_approved(&reviews(&[
+            ("alice", "CHANGES_REQUESTED"),
+            ("alice", "APPROVED"),
+            ("bob", "COMMENTED"),
+        ])));
+        assert!(!github_reviews_approved(&reviews(&[
+            ("alice", "APPROVED"),
+            ("bob", "CHANGES_REQUESTED"),
+        ])));
+        assert!(!github_reviews_approved(&reviews(&[("bob", "COMMENTED")])));
+    }
+
+    #[test]
+    fn test_github_checks_green() {
+        let no_statuses = GitHubCombinedStatus {
+            state: String::from("pending"),
+            total_count: 0,
+        };
+        let passed: GitHubCheckRuns = serde_json::from_str(
+            r#"{"check_runs": [
+                {"status": "completed", "conclusion": "success"},
+                {"status": "completed", "conclusion": "skipped"}
+            ]}"#,
+        )
+        .unwrap();
+        assert!(github_checks_green(&no_statuses, &passed));
+        let running: GitHubCheckRuns = serde_json::from_str(
+            r#"{"check_runs": [{"status": "in_progress", "conclusion": null}]}"#,
+        )
+        .unwrap();
+        assert!(!github_checks_green(&no_statuses, &running));
+        let none = GitHubCheckRuns { check_runs: vec![] };
+        assert!(!github_checks_green(&no_statuses, &none));
+    }
+
+    #[test]
+    fn test_github_checks_running() {
+        let no_statuses = GitHubCombinedStatus {
+            state: String::from("pending"),
+            total_count: 0,
+        };
+        let pending_status = GitHubCombinedStatus {
+            state: String::from("pending"),
+            total_count: 2,
+        };
+        let none = GitHubCheckRuns { check_runs: vec![] };
+        assert!(!github_checks_running(&no_statuses, &none));
+        assert!(github_checks_running(&pending_status, &none));
+        let queued: GitHubCheckRuns = serde_json::from_str(
+            r#"{"check_runs": [
+                {"status": "completed", "conclusion": "success"},
+                {"status": "queued", "conclusion": null}
+            ]}"#,
+        )
+        .unwrap();
+        assert!(github_checks_running(&no_statuses, &queued));
+    }
+
+    #[test]
+    fn test_parse_github_url_browser_shapes() {
+        let project = String::from("my_org/my_project");
+        let urls = [
+            "https://github.com/my_org/my_project",
+            "https://github.com/my_org/my_project/",
+            "https://github.com/my_org/my_project/tree/main",
+            "https://github.com/my_org/my_project/tree/feature/nested-branch",
+            "https://github.com/my_org/my_project/blob/main/src/main.rs",
+            "https://github.com/my_org/my_project?tab=readme",
+        ];
+        for url in urls.iter() {
+            assert_eq!(
+                Some((project.clone(), None)),
+                parse_github_url(url),
+                "{}",
+                url
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_github_url_pull_request() {
+        let expected = Some((String::from("my_org/my_project"), Some(42)));
+        let urls = [
+            "https://github.com/my_org/my_project/pull/42",
+            "https://github.com/my_org/my_project/pull/42/files",
+            "https://github.com/my_org/my_project/pull/42#discussion_r1",
+        ];
+        for url in urls.iter() {
+            assert_eq!(expected, parse_github_url(url), "{}", url);
+        }
+    }
+
+    #[test]
+    fn test_get_github_project_name_ssh_url() {
+        let name = get_github_project_name("ssh://git@github.com/my_org/my_project.git");
+        assert_eq!("my_org/my_project", name);
+    }
 }
@@ -0,0 +1,301 @@
+use crate::git;
+use log::{debug, trace};
+use openssl::hash::MessageDigest;
+use openssl::pkey::PKey;
+use openssl::sign::Signer;
+use serde_derive::Deserialize;
+use serde_json::json;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// How long before an installation token expires that it's replaced
+const REFRESH_MARGIN_SECS: u64 = 60;
+
+/// Authenticates as a GitHub App installation (`req.<domain>.appid` and `req.<domain>.appkey`).
+/// The private key is read from disk whenever a token is minted and never kept or logged.
+pub struct AppAuth {
+    app_id: String,
+    key_path: PathBuf,
+    api_base: String,
+    project: String,
+    token: Mutex<InstallationToken>,
+}
+
+#[derive(Clone)]
+struct InstallationToken {
+    token: String,
+    /// Seconds since the Unix epoch
+    expires_at: u64,
+}
+
+#[derive(Deserialize, Debug)]
+struct GitHubInstallation {
+    id: i64,
+}
+
+#[derive(Deserialize)]
+struct GitHubAccessToken {
+    token: String,
+    expires_at: String,
+}
+
+impl fmt::Debug for AppAuth {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("AppAuth")
+            .field("app_id", &self.app_id)
+            .field("key_path", &self.key_path)
+            .field("project", &self.project)
+            .finish()
+    }
+}
+
+impl AppAuth {
+    /// Set up App authentication for the project if it's configured for the domain, minting
+    /// the first installation token
+    pub fn from_config(
+        domain: &str,
+        api_root: &str,
+        project: &str,
+    ) -> Result<Option<AppAuth>, String> {
+        let (app_id, key_path) = match (
+            git::get_req_config(domain, "appid"),
+            git::get_req_config(domain, "appkey"),
+        ) {
+            (None, None) => return Ok(None),
+            (Some(app_id), Some(key_path)) => (app_id, key_path),
+            _ => {
+                return Err(format!(
+                    "GitHub App authentication needs both req.{0}.appid and req.{0}.appkey",
+                    git::slugify_domain(domain)
+                ))
+            }
+        };
+        let key_path = PathBuf::from(shellexpand::tilde(&key_path).to_string());
+        let api_base = api_root.trim_end_matches("/repos").to_string();
+        let token = mint_installation_token(&app_id, &key_path, &api_base, project)?;
+        Ok(Some(AppAuth {
+            app_id,
+            key_path,
+            api_base,
+            project: String::from(project),
+            token: Mutex::new(token),
+        }))
+    }
+
+    /// Get an installation token, minting a new one if the current one is about to expire
+    pub fn token(&self) -> String {
+        let mut current = self.token.lock().unwrap();
+        if current.expires_at > now() + REFRESH_MARGIN_SECS {
+            return current.token.clone();
+        }
+        debug!("GitHub App installation token expired; minting a new one");
+        match mint_installation_token(&self.app_id, &self.key_path, &self.api_base, &self.project) {
+            Ok(token) => *current = token,
+            Err(error) => eprintln!(
+                "Warning: couldn't refresh the GitHub App installation token: {}",
+                error
+            ),
+        }
+        current.token.clone()
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0)
+}
+
+/// Exchange a JWT signed with the App's private key for a token for the project's installation
+fn mint_installation_token(
+    app_id: &str,
+    key_path: &Path,
+    api_base: &str,
+    project: &str,
+) -> Result<InstallationToken, String> {
+    trace!("Minting a GitHub App installation token for {}", project);
+    let jwt = app_jwt(app_id, &read_private_key(key_path)?, now())?;
+    let client = reqwest::Client::new();
+    let mut resp = client
+        .get(&format!("{}/repos/{}/installation", api_base, project))
+        .bearer_auth(&jwt)
+        .header("Accept", "application/vnd.github.machine-man-preview+json")
+        .send()
+        .map_err(|error| format!("couldn't reach GitHub: {}", error))?;
+    if !resp.status().is_success() {
+        return Err(format!(
+            "the App isn't installed for {} (the API responded with {})",
+            project,
+            resp.status()
+        ));
+    }
+    let installation: GitHubInstallation = resp
+        .json()
+        .map_err(|_| String::from("failed to read the installation"))?;
+    debug!("Found installation {} for {}", installation.id, project);
+    let mut resp = client
+        .post(&format!(
+            "{}/app/installations/{}/access_tokens",
+            api_base, installation.id
+        ))
+        .bearer_auth(&jwt)
+        .header("Accept", "application/vnd.github.machine-man-preview+json")
+        .send()
+        .map_err(|error| format!("couldn't reach GitHub: {}", error))?;
+    if !resp.status().is_success() {
+        return Err(format!(
+            "couldn't create an installation token (the API responded with {})",
+            resp.status()
+        ));
+    }
+    let access: GitHubAccessToken = resp
+        .json()
+        .map_err(|_| String::from("failed to read the installation token"))?;
+    let expires_at = parse_timestamp(&access.expires_at)
+        .ok_or_else(|| format!("unexpected token expiry {:?}", access.expires_at))?;
+    Ok(InstallationToken {
+        token: access.token,
+        expires_at,
+    })
+}
+
+/// Read the App's PEM private key, warning if other users can read it
+fn read_private_key(key_path: &Path) -> Result<Vec<u8>, String> {
+    let key = fs::read(key_path).map_err(|error| {
+        format!(
+            "couldn't read the GitHub App private key {}: {}",
+            key_path.display(),
+            error
+        )
+    })?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        if let Ok(metadata) = fs::metadata(key_path) {
+            if metadata.permissions().mode() & 0o077 != 0 {
+                eprintln!(
+                    "Warning: the GitHub App private key {} is accessible by other users",
+                    key_path.display()
+                );
+            }
+        }
+    }
+    Ok(key)
+}
+
+/// Build the short-lived JWT an App authenticates with, signed with its private key (RS256)
+fn app_jwt(app_id: &str, private_key_pem: &[u8], now: u64) -> Result<String, String> {
+    let header = json!({"alg": "RS256", "typ": "JWT"});
+    // Backdated to allow for clock drift; GitHub accepts at most ten minutes
+    let claims = json!({"iat": now.saturating_sub(60), "exp": now + 540, "iss": app_id});
+    let signing_input = format!(
+        "{}.{}",
+        base64::encode_config(&header.to_string(), base64::URL_SAFE_NO_PAD),
+        base64::encode_config(&claims.to_string(), base64::URL_SAFE_NO_PAD)
+    );
+    let invalid_key = |_| String::from("the GitHub App private key isn't a valid PEM RSA key");
+    let key = PKey::private_key_from_pem(private_key_pem).map_err(invalid_key)?;
+    let mut signer = Signer::new(MessageDigest::sha256(), &key).map_err(invalid_key)?;
+    signer
+        .update(signing_input.as_bytes())
+        .map_err(invalid_key)?;
+    let signature = signer.sign_to_vec().map_err(invalid_key)?;
+    Ok(format!(
+        "{}.{}",
+        signing_input,
+        base64::encode_config(&signature, base64::URL_SAFE_NO_PAD)
+    ))
+}
+
+/// Parse a UTC timestamp like `2016-07-11T22:14:10Z` into seconds since the Unix epoch
+fn parse_timestamp(timestamp: &str) -> Option<u64> {
+    let timestamp = timestamp.strip_suffix('Z')?;
+    let (date, time) = timestamp.split_at(timestamp.find('T')?);
+    let date: Vec<i64> = date
+        .split('-')
+        .map(|part| part.parse().ok())
+        .collect::<Option<_>>()?;
+    let time: Vec<i64> = time[1..]
+        .split(':')
+        .map(|part| part.parse().ok())
+        .collect::<Option<_>>()?;
+    if date.len() != 3 || time.len() != 3 {
+        return None;
+    }
+    // Days since the epoch for the proleptic Gregorian calendar
+    let (year, month, day) = (date[0], date[1], date[2]);
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146_097 + day_of_era - 719_468;
+    let seconds = days * 86_400 + time[0] * 3_600 + time[1] * 60 + time[2];
+    if seconds < 0 {
+        None
+    } else {
+        Some(seconds as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use openssl::rsa::Rsa;
+    use openssl::sign::Verifier;
+
+    #[test]
+    fn test_app_jwt() {
+        let key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+        let pem = key.private_key_to_pem_pkcs8().unwrap();
+        let jwt = app_jwt("1234", &pem, 1_000_000).unwrap();
+        let parts: Vec<&str> = jwt.split('.').collect();
+        assert_eq!(3, parts.len());
+        let decode = |part: &str| base64::decode_config(part, base64::URL_SAFE_NO_PAD).unwrap();
+        let header: serde_json::Value = serde_json::from_slice(&decode(parts[0])).unwrap();
+        assert_eq!("RS256", header["alg"]);
+        let claims: serde_json::Value = serde_json::from_slice(&decode(parts[1])).unwrap();
+        assert_eq!(
+            json!({"iat": 999_940, "exp": 1_000_540, "iss": "1234"}),
+            claims
+        );
+        let mut verifier = Verifier::new(MessageDigest::sha256(), &key).unwrap();
+        verifier
+            .update(format!("{}.{}", parts[0], parts[1]).as_bytes())
+            .unwrap();
+        assert!(verifier.verify(&decode(parts[2])).unwrap());
+    }
+
+    #[test]
+    fn test_app_jwt_invalid_key() {
+        assert!(app_jwt("1234", b"not a key", 1_000_000).is_err());
+    }
+
+    #[test]
+    fn test_parse_timestamp() {
+        assert_eq!(Some(0), parse_timestamp("1970-01-01T00:00:00Z"));
+        assert_eq!(Some(1_468_275_250), parse_timestamp("2016-07-11T22:14:10Z"));
+        assert_eq!(Some(951_782_400), parse_timestamp("2000-02-29T00:00:00Z"));
+        assert_eq!(None, parse_timestamp("2016-07-11 22:14:10"));
+        assert_eq!(None, parse_timestamp("2016-07-11T22:14Z"));
+    }
+
+    #[test]
+    fn test_debug_leaves_out_the_token() {
+        let auth = AppAuth {
+            app_id: String::from("1234"),
+            key_path: PathBuf::from("/keys/app.pem"),
+            api_base: String::from("https://api.github.com"),
+            project: String::from("my_org/my_project"),
+            token: Mutex::new(InstallationToken {
+                token: String::from("ghs_s3cr3t"),
+                expires_at: 0,
+            }),
+        };
+        assert!(!format!("{:?}", auth).contains("ghs_s3cr3t"));
+    }
+}
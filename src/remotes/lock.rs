@@ -0,0 +1,192 @@
+use crate::git;
+use crate::remotes::{describe_project, get_domain, get_project_path, Provider};
+use log::debug;
+use serde_derive::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+
+/// The lockfile's name, at the root of the working tree
+pub const LOCKFILE_NAME: &str = "git-req.lock";
+
+/// How a project was resolved, recorded in `git-req.lock` so every checkout of the repository
+/// talks to the same project without looking it up again. It never holds credentials, and the
+/// API root is always derived from the domain rather than recorded, so a committed lock can't
+/// send API keys to another host.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct Lock {
+    /// The project the lock was made for, e.g. `gitlab.com/my_namespace/my_project`
+    pub project: String,
+    pub provider: String,
+    pub project_id: String,
+}
+
+impl Lock {
+    /// Parse a lockfile's contents
+    pub fn parse(contents: &str) -> Result<Lock, String> {
+        let lock: Lock = serde_json::from_str(contents).map_err(|error| error.to_string())?;
+        lock.provider()?;
+        Ok(lock)
+    }
+
+    /// Get the provider the lock pins
+    pub fn provider(&self) -> Result<Provider, String> {
+        Provider::from_name(&self.provider)
+            .ok_or_else(|| format!("unknown provider {:?}", self.provider))
+    }
+
+    /// Check whether the lock was made for the project the remote URL points at
+    pub fn matches(&self, origin: &str) -> bool {
+        describe_project(origin).as_deref() == Some(self.project.as_str())
+    }
+
+    /// Check whether the lock's provider is the one the remote URL's domain is detected as
+    pub fn provider_matches(&self, origin: &str) -> bool {
+        get_domain(origin).map(Provider::detect).ok() == self.provider().ok()
+    }
+
+    /// Get the project ID to use instead of resolving it. Only GitLab's numeric IDs are taken
+    /// from the lock: on GitHub and Bitbucket the ID is the project path, which is always read
+    /// from the remote URL so a lock can't point the API at another repository.
+    pub fn pinned_project_id(&self) -> Option<&str> {
+        match self.provider() {
+            Ok(Provider::GitLab) => Some(&self.project_id),
+            _ => None,
+        }
+    }
+
+    /// Render the lock as the lockfile's contents
+    pub fn to_json(&self) -> String {
+        format!("{}\n", serde_json::to_string_pretty(self).unwrap())
+    }
+}
+
+/// Get the path of the repository's lockfile
+pub fn lockfile_path() -> Option<PathBuf> {
+    git::workdir().map(|workdir| workdir.join(LOCKFILE_NAME))
+}
+
+/// Load the repository's lock, if it has one that's valid for the remote URL
+pub fn load(origin: &str) -> Option<Lock> {
+    let path = lockfile_path()?;
+    let contents = fs::read_to_string(&path).ok()?;
+    let lock = match Lock::parse(&contents) {
+        Ok(lock) => lock,
+        Err(error) => {
+            eprintln!(
+                "Warning: ignoring {}, which couldn't be read: {}",
+                LOCKFILE_NAME, error
+            );
+            return None;
+        }
+    };
+    if !lock.matches(origin) {
+        debug!(
+            "Ignoring {}, which is for {} rather than this remote",
+            LOCKFILE_NAME, lock.project
+        );
+        return None;
+    }
+    if !lock.provider_matches(origin) {
+        eprintln!(
+            "Warning: ignoring {}, whose provider {} isn't the one this remote uses",
+            LOCKFILE_NAME, lock.provider
+        );
+        return None;
+    }
+    if lock.pinned_project_id().is_none()
+        && get_project_path(origin).as_deref() != Some(lock.project_id.as_str())
+    {
+        eprintln!(
+            "Warning: ignoring the project ID {} in {}, which isn't this remote's project",
+            lock.project_id, LOCKFILE_NAME
+        );
+    }
+    debug!("Using {}: {:?}", LOCKFILE_NAME, lock);
+    Some(lock)
+}
+
+/// Write the repository's lockfile
+pub fn write(lock: &Lock) -> Result<PathBuf, String> {
+    let path = lockfile_path()
+        .ok_or_else(|| format!("There's no working tree to write {} in", LOCKFILE_NAME))?;
+    fs::write(&path, lock.to_json())
+        .map_err(|error| format!("Couldn't write {}: {}", path.display(), error))?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_lock() -> Lock {
+        Lock {
+            project: String::from("gitlab.com/group/subgroup/project"),
+            provider: String::from("GitLab"),
+            project_id: String::from("4321"),
+        }
+    }
+
+    #[test]
+    fn test_lock_round_trip() {
+        let lock = test_lock();
+        assert_eq!(Ok(test_lock()), Lock::parse(&lock.to_json()));
+        assert_eq!(Ok(Provider::GitLab), lock.provider());
+    }
+
+    #[test]
+    fn test_lock_invalid() {
+        assert!(Lock::parse("{}").is_err());
+        assert!(Lock::parse("not json").is_err());
+        let json = test_lock().to_json().replace("GitLab", "Gitea");
+        assert!(Lock::parse(&json).is_err());
+    }
+
+    #[test]
+    fn test_lock_matches() {
+        let lock = test_lock();
+        assert!(lock.matches("git@gitlab.com:group/subgroup/project.git"));
+        assert!(lock.matches("https://gitlab.com/group/subgroup/project.git"));
+        assert!(!lock.matches("git@gitlab.com:group/subgroup/other.git"));
+        assert!(!lock.matches("git@gitlab.example.com:group/subgroup/project.git"));
+    }
+
+    #[test]
+    fn test_lock_provider_matches() {
+        let origin = "git@gitlab.com:group/subgroup/project.git";
+        assert!(test_lock().provider_matches(origin));
+        let lock = Lock {
+            provider: String::from("GitHub"),
+            ..test_lock()
+        };
+        assert!(!lock.provider_matches(origin));
+    }
+
+    #[test]
+    fn test_lock_only_pins_gitlab_project_ids() {
+        assert_eq!(Some("4321"), test_lock().pinned_project_id());
+        let lock = Lock {
+            project: String::from("github.com/alice/project"),
+            provider: String::from("GitHub"),
+            project_id: String::from("mallory/other"),
+        };
+        assert_eq!(None, lock.pinned_project_id());
+        let lock = Lock {
+            provider: String::from("Bitbucket"),
+            ..lock
+        };
+        assert_eq!(None, lock.pinned_project_id());
+    }
+
+    #[test]
+    fn test_lock_ignores_api_root() {
+        let json = r#"{
+            "project": "gitlab.com/group/subgroup/project",
+            "provider": "GitLab",
+            "api_root": "https://attacker.example.com/api/v4",
+            "project_id": "4321"
+        }"#;
+        let lock = Lock::parse(json).unwrap();
+        assert_eq!(test_lock(), lock);
+        assert!(!lock.to_json().contains("api_root"));
+    }
+}
@@ -0,0 +1,240 @@
+use crate::git;
+use log::debug;
+use regex::Regex;
+use std::thread;
+use std::time::Duration;
+
+/// The highest number of retries that may be configured
+const MAX_RETRIES: u32 = 10;
+
+/// The longest initial backoff that may be configured, and the longest wait between retries
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// How long a diff or changed-files request may take unless configured otherwise. Providers
+/// can be slow to generate the diffs of big requests, so this is well above the usual 30s.
+const DEFAULT_DIFF_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// How failed API requests are retried
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RetryPolicy {
+    /// How many times a failed request is retried
+    pub retries: u32,
+    /// How long to wait before the first retry; doubled for each one after, up to a minute
+    pub backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> RetryPolicy {
+        RetryPolicy {
+            retries: 2,
+            backoff: Duration::from_millis(500),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Load the retry policy for the domain. `req.<domain>.retries` and `req.<domain>.backoff`
+    /// in the global git-req config override `req.retries` and `req.backoff`.
+    pub fn for_domain(domain: &str) -> RetryPolicy {
+        RetryPolicy::default()
+            .with_overrides(git::get_config("retries"), git::get_config("backoff"))
+            .with_overrides(
+                git::get_req_config(domain, "retries"),
+                git::get_req_config(domain, "backoff"),
+            )
+    }
+
+    /// Apply the configured values on top of this policy, ignoring any that are invalid
+    fn with_overrides(self, retries: Option<String>, backoff: Option<String>) -> RetryPolicy {
+        let mut policy = self;
+        if let Some(retries) = retries {
+            match parse_retries(&retries) {
+                Ok(retries) => policy.retries = retries,
+                Err(error) => eprintln!("Warning: ignoring retries setting: {}", error),
+            }
+        }
+        if let Some(backoff) = backoff {
+            match parse_backoff(&backoff) {
+                Ok(backoff) => policy.backoff = backoff,
+                Err(error) => eprintln!("Warning: ignoring backoff setting: {}", error),
+            }
+        }
+        policy
+    }
+
+    /// Get how long to wait before the given retry (counting from zero)
+    fn delay(&self, attempt: u32) -> Duration {
+        (self.backoff * 2u32.pow(attempt.min(16))).min(MAX_BACKOFF)
+    }
+}
+
+/// Parse a retry count
+pub fn parse_retries(value: &str) -> Result<u32, String> {
+    match value.trim().parse::<u32>() {
+        Ok(retries) if retries <= MAX_RETRIES => Ok(retries),
+        _ => Err(format!(
+            "expected a number of retries from 0 to {}, got {:?}",
+            MAX_RETRIES, value
+        )),
+    }
+}
+
+/// Parse a backoff duration, e.g. `500ms` or `2s`. Bare numbers are seconds.
+pub fn parse_backoff(value: &str) -> Result<Duration, String> {
+    match parse_duration(value) {
+        Some(duration) if duration <= MAX_BACKOFF => Ok(duration),
+        _ => Err(format!(
+            "expected a duration of at most {}s such as 500ms or 2s, got {:?}",
+            MAX_BACKOFF.as_secs(),
+            value
+        )),
+    }
+}
+
+/// Parse a timeout, e.g. `90s` or `600`. Bare numbers are seconds.
+pub fn parse_timeout(value: &str) -> Result<Duration, String> {
+    match parse_duration(value) {
+        Some(duration) if duration > Duration::from_secs(0) => Ok(duration),
+        _ => Err(format!(
+            "expected a timeout such as 90s or 600, got {:?}",
+            value
+        )),
+    }
+}
+
+/// Parse a duration in milliseconds (`500ms`) or seconds (`2s` or `2`)
+fn parse_duration(value: &str) -> Option<Duration> {
+    let duration_regex = Regex::new(r"^(?P<amount>\d+)(?P<unit>ms|s)?$").unwrap();
+    let captures = duration_regex.captures(value.trim())?;
+    let amount: u64 = captures["amount"].parse().ok()?;
+    Some(match captures.name("unit").map(|unit| unit.as_str()) {
+        Some("ms") => Duration::from_millis(amount),
+        _ => Duration::from_secs(amount),
+    })
+}
+
+/// Get the timeout for the domain's diff and changed-files requests. `req.<domain>.difftimeout`
+/// in the global git-req config overrides `req.difftimeout`.
+pub fn diff_timeout(domain: &str) -> Duration {
+    let configured =
+        git::get_req_config(domain, "difftimeout").or_else(|| git::get_config("difftimeout"));
+    match configured.map(|value| parse_timeout(&value)) {
+        Some(Ok(timeout)) => timeout,
+        Some(Err(error)) => {
+            eprintln!("Warning: ignoring diff timeout setting: {}", error);
+            DEFAULT_DIFF_TIMEOUT
+        }
+        None => DEFAULT_DIFF_TIMEOUT,
+    }
+}
+
+/// Build a client for the domain's diff and changed-files requests, with their longer timeout
+pub fn diff_client(domain: &str) -> reqwest::Client {
+    reqwest::Client::builder()
+        .timeout(diff_timeout(domain))
+        .build()
+        .expect("failed to set up the HTTP client")
+}
+
+/// Send a request built by `build`, retrying connection failures and server errors according
+/// to the domain's retry policy. Fails if the last attempt couldn't get a response at all.
+pub fn send_with_retry<F>(domain: &str, build: F) -> Result<reqwest::Response, String>
+where
+    F: Fn() -> reqwest::RequestBuilder,
+{
+    let policy = RetryPolicy::for_domain(domain);
+    let mut attempt = 0;
+    loop {
+        let result = build().send();
+        let retryable = match result {
+            Ok(ref resp) => {
+                resp.status().is_server_error()
+                    || resp.status() == reqwest::StatusCode::TOO_MANY_REQUESTS
+            }
+            Err(_) => true,
+        };
+        if !retryable || attempt >= policy.retries {
+            return result.map_err(|error| format!("couldn't reach {}: {}", domain, error));
+        }
+        let delay = policy.delay(attempt);
+        debug!(
+            "Request to {} failed ({:?}), retrying in {:?}",
+            domain,
+            result.map(|resp| resp.status()),
+            delay
+        );
+        thread::sleep(delay);
+        attempt += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_retries() {
+        assert_eq!(Ok(3), parse_retries("3"));
+        assert!(parse_retries("-1").is_err());
+        assert!(parse_retries("many").is_err());
+        assert!(parse_retries("11").is_err());
+    }
+
+    #[test]
+    fn test_parse_backoff() {
+        assert_eq!(Ok(Duration::from_millis(250)), parse_backoff("250ms"));
+        assert_eq!(Ok(Duration::from_secs(2)), parse_backoff("2s"));
+        assert_eq!(Ok(Duration::from_secs(2)), parse_backoff("2"));
+        assert!(parse_backoff("2m").is_err());
+        assert!(parse_backoff("61s").is_err());
+        assert!(parse_backoff("soon").is_err());
+    }
+
+    #[test]
+    fn test_parse_timeout() {
+        assert_eq!(Ok(Duration::from_secs(600)), parse_timeout("600"));
+        assert_eq!(Ok(Duration::from_secs(90)), parse_timeout(" 90s "));
+        assert_eq!(Ok(Duration::from_millis(1500)), parse_timeout("1500ms"));
+        assert!(parse_timeout("0").is_err());
+        assert!(parse_timeout("10m").is_err());
+    }
+
+    #[test]
+    fn test_domain_overrides_take_precedence() {
+        let policy = RetryPolicy::default()
+            .with_overrides(Some(String::from("1")), Some(String::from("1s")))
+            .with_overrides(Some(String::from("5")), Some(String::from("100ms")));
+        assert_eq!(5, policy.retries);
+        assert_eq!(Duration::from_millis(100), policy.backoff);
+    }
+
+    #[test]
+    fn test_invalid_domain_overrides_fall_back_to_global() {
+        let policy = RetryPolicy::default()
+            .with_overrides(Some(String::from("1")), Some(String::from("1s")))
+            .with_overrides(Some(String::from("lots")), None);
+        assert_eq!(1, policy.retries);
+        assert_eq!(Duration::from_secs(1), policy.backoff);
+    }
+
+    #[test]
+    fn test_delay_doubles() {
+        let policy = RetryPolicy {
+            retries: 3,
+            backoff: Duration::from_millis(100),
+        };
+        assert_eq!(Duration::from_millis(100), policy.delay(0));
+        assert_eq!(Duration::from_millis(400), policy.delay(2));
+    }
+
+    #[test]
+    fn test_delay_is_capped() {
+        let policy = RetryPolicy {
+            retries: MAX_RETRIES,
+            backoff: Duration::from_secs(10),
+        };
+        assert_eq!(Duration::from_secs(40), policy.delay(2));
+        assert_eq!(MAX_BACKOFF, policy.delay(3));
+        assert_eq!(MAX_BACKOFF, policy.delay(MAX_RETRIES));
+    }
+}
@@ -0,0 +1,147 @@
+use crate::remotes::MergeRequest;
+
+/// Find the request each one is stacked on: the first request from the same project whose
+/// head branch is its target branch
+fn parents(mrs: &[MergeRequest]) -> Vec<Option<usize>> {
+    mrs.iter()
+        .enumerate()
+        .map(|(index, mr)| {
+            mrs.iter()
+                .position(|parent| {
+                    // A fork's branch can't be targeted, even if it has the same name
+                    parent.source_project.is_none() && parent.head_branch == mr.target_branch
+                })
+                .filter(|&parent| parent != index)
+        })
+        .collect()
+}
+
+/// Arrange requests into stacks, with each request under the one whose branch it targets.
+/// Returns the requests' indices in the order to show them, each with the tree drawing to
+/// show before it. Otherwise the listing order is kept; requests that only target each other
+/// in a cycle are shown from the first of them.
+pub fn arrange(mrs: &[MergeRequest]) -> Vec<(usize, String)> {
+    let parents = parents(mrs);
+    let mut shown = vec![false; mrs.len()];
+    let mut lines = vec![];
+    let roots = (0..mrs.len()).filter(|&index| parents[index].is_none());
+    for root in roots.chain(0..mrs.len()) {
+        if !shown[root] {
+            visit(&parents, root, String::new(), "", &mut shown, &mut lines);
+        }
+    }
+    lines
+}
+
+fn visit(
+    parents: &[Option<usize>],
+    index: usize,
+    drawing: String,
+    indent: &str,
+    shown: &mut [bool],
+    lines: &mut Vec<(usize, String)>,
+) {
+    shown[index] = true;
+    lines.push((index, drawing));
+    let children: Vec<usize> = (0..parents.len())
+        .filter(|&child| parents[child] == Some(index) && !shown[child])
+        .collect();
+    for (position, &child) in children.iter().enumerate() {
+        let (branch, continuation) = if position + 1 == children.len() {
+            ("└─ ", "   ")
+        } else {
+            ("├─ ", "│  ")
+        };
+        let drawing = format!("{}{}", indent, branch);
+        let indent = format!("{}{}", indent, continuation);
+        visit(parents, child, drawing, &indent, shown, lines);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mr(id: i64, source_branch: &str, target_branch: &str) -> MergeRequest {
+        MergeRequest {
+            id,
+            title: format!("Request {}", id),
+            description: None,
+            source_branch: String::from(source_branch),
+            head_branch: String::from(source_branch),
+            target_branch: String::from(target_branch),
+            source_project: None,
+            head_sha: None,
+            url: format!("https://gitlab.com/group/project/merge_requests/{}", id),
+            draft: false,
+            author: None,
+            labels: vec![],
+            assignees: vec![],
+        }
+    }
+
+    fn render(mrs: &[MergeRequest]) -> Vec<String> {
+        arrange(mrs)
+            .into_iter()
+            .map(|(index, drawing)| format!("{}{}", drawing, mrs[index].id))
+            .collect()
+    }
+
+    #[test]
+    fn test_arrange_stacks() {
+        let mrs = vec![
+            mr(4, "api-client", "api-models"),
+            mr(1, "api-models", "main"),
+            mr(2, "typo", "main"),
+            mr(5, "api-docs", "api-models"),
+            mr(6, "api-retries", "api-client"),
+            mr(3, "backport", "release-1.2"),
+        ];
+        assert_eq!(vec!["1", "├─ 4", "│  └─ 6", "└─ 5", "2", "3"], render(&mrs));
+    }
+
+    #[test]
+    fn test_arrange_flat() {
+        let mrs = vec![mr(1, "a", "main"), mr(2, "b", "main")];
+        assert_eq!(vec!["1", "2"], render(&mrs));
+        assert!(arrange(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_arrange_ignores_fork_branches() {
+        let fork = MergeRequest {
+            source_project: Some(String::from("alice/project")),
+            ..mr(1, "feature", "main")
+        };
+        let mrs = vec![fork, mr(2, "feature-tests", "feature")];
+        assert_eq!(vec!["1", "2"], render(&mrs));
+    }
+
+    #[test]
+    fn test_arrange_github_stacks() {
+        // GitHub requests are fetched as pr/<#>, so they stack by their head branches
+        let github = |id, head_branch: &str, target_branch| MergeRequest {
+            source_branch: format!("pr/{}", id),
+            head_branch: String::from(head_branch),
+            url: format!("https://github.com/owner/project/pull/{}", id),
+            ..mr(id, "", target_branch)
+        };
+        let mrs = vec![
+            github(2, "api-client", "api-models"),
+            github(1, "api-models", "main"),
+            github(3, "docs", "pr/1"),
+        ];
+        assert_eq!(vec!["1", "└─ 2", "3"], render(&mrs));
+    }
+
+    #[test]
+    fn test_arrange_cycle() {
+        let mrs = vec![
+            mr(1, "a", "b"),
+            mr(2, "b", "a"),
+            mr(3, "c", "a"),
+            mr(4, "d", "main"),
+        ];
+        assert_eq!(vec!["4", "1", "├─ 2", "└─ 3"], render(&mrs));
+    }
+}
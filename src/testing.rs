@@ -0,0 +1,9 @@
+/// Fake an environment holding only the given variables, as a lookup function for code that
+/// takes one instead of reading the real environment
+pub fn env(vars: &'static [(&'static str, &'static str)]) -> impl Fn(&str) -> Option<String> {
+    move |name| {
+        vars.iter()
+            .find(|(key, _)| *key == name)
+            .map(|(_, value)| String::from(*value))
+    }
+}
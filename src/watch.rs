@@ -0,0 +1,174 @@
+use crate::remotes::RequestStatus;
+use duct::cmd;
+use log::debug;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How often to poll if neither `--interval` nor `req.watchinterval` says otherwise
+pub const DEFAULT_INTERVAL_SECS: u64 = 30;
+/// The shortest interval allowed, to stay well clear of the providers' rate limits
+pub const MIN_INTERVAL_SECS: u64 = 10;
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Parse the polling interval in seconds, raising it to the minimum if it's shorter
+pub fn interval(value: Option<&str>) -> Result<u64, String> {
+    let secs = match value {
+        None => DEFAULT_INTERVAL_SECS,
+        Some(value) => value.trim().parse().map_err(|_| {
+            format!(
+                "The watch interval should be a number of seconds, not {:?}",
+                value
+            )
+        })?,
+    };
+    Ok(secs.max(MIN_INTERVAL_SECS))
+}
+
+/// Summarize a request's merge gates on one line
+pub fn status_line(status: &RequestStatus) -> String {
+    let pipeline = match (status.pipeline_running, status.pipeline_green) {
+        (Some(true), _) => "pipeline running",
+        (_, Some(true)) => "pipeline passed",
+        (_, Some(false)) => "pipeline not passed",
+        (_, None) => "pipeline unknown",
+    };
+    let approval = match status.approved {
+        Some(true) => "approved",
+        Some(false) => "not approved",
+        None => "approval unknown",
+    };
+    let mergeable = match status.mergeable {
+        Some(true) => "mergeable",
+        Some(false) => "not mergeable",
+        None => "mergeability unknown",
+    };
+    format!("{} | {} | {}", pipeline, approval, mergeable)
+}
+
+/// Describe the changes between two polls worth a notification: the request becoming
+/// mergeable, or its pipeline finishing
+pub fn notable_changes(previous: &RequestStatus, current: &RequestStatus) -> Vec<&'static str> {
+    let mut changes = vec![];
+    if previous.pipeline_running == Some(true) && current.pipeline_running == Some(false) {
+        changes.push(match current.pipeline_green {
+            Some(true) => "the pipeline passed",
+            _ => "the pipeline finished without passing",
+        });
+    }
+    if previous.mergeable != Some(true) && current.mergeable == Some(true) {
+        changes.push("it's mergeable");
+    }
+    changes
+}
+
+/// Ring the terminal bell and, where there's a desktop, show a notification too
+pub fn notify(title: &str, message: &str, desktop: bool) {
+    eprint!("\x07");
+    if !desktop {
+        return;
+    }
+    let expression = if cfg!(target_os = "macos") {
+        let script = format!("display notification {:?} with title {:?}", message, title);
+        cmd!("osascript", "-e", script)
+    } else if cfg!(windows) {
+        return;
+    } else {
+        cmd!("notify-send", title, message)
+    };
+    if let Err(error) = expression.stdout_null().stderr_null().run() {
+        debug!("Couldn't show a desktop notification: {}", error);
+    }
+}
+
+/// Stop the first Ctrl-C from killing the process, so the watch loop can finish cleanly
+/// instead. The handler is only used once: a second Ctrl-C exits straight away, even while a
+/// poll is still waiting on the provider.
+pub fn catch_interrupts() {
+    #[cfg(unix)]
+    {
+        extern "C" fn on_interrupt(_: libc::c_int) {
+            INTERRUPTED.store(true, Ordering::SeqCst);
+        }
+        // SAFETY: the handler only stores to an atomic, which is async-signal-safe, and the
+        // zeroed action is valid with its handler and flags set
+        unsafe {
+            let mut action: libc::sigaction = std::mem::zeroed();
+            action.sa_sigaction = on_interrupt as extern "C" fn(libc::c_int) as libc::sighandler_t;
+            action.sa_flags = libc::SA_RESETHAND;
+            libc::sigaction(libc::SIGINT, &action, std::ptr::null_mut());
+        }
+    }
+}
+
+/// Wait for `secs` seconds, returning early with `false` if Ctrl-C is pressed
+pub fn sleep_unless_interrupted(secs: u64) -> bool {
+    let deadline = Instant::now() + Duration::from_secs(secs);
+    while Instant::now() < deadline {
+        if INTERRUPTED.load(Ordering::SeqCst) {
+            return false;
+        }
+        thread::sleep(Duration::from_millis(200));
+    }
+    !INTERRUPTED.load(Ordering::SeqCst)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn status(
+        mergeable: Option<bool>,
+        pipeline_green: Option<bool>,
+        pipeline_running: Option<bool>,
+    ) -> RequestStatus {
+        RequestStatus {
+            mergeable,
+            approved: Some(true),
+            pipeline_green,
+            pipeline_running,
+        }
+    }
+
+    #[test]
+    fn test_interval() {
+        assert_eq!(Ok(DEFAULT_INTERVAL_SECS), interval(None));
+        assert_eq!(Ok(45), interval(Some("45")));
+        assert_eq!(Ok(MIN_INTERVAL_SECS), interval(Some("1")));
+        assert!(interval(Some("soon")).is_err());
+    }
+
+    #[test]
+    fn test_status_line() {
+        assert_eq!(
+            "pipeline running | approved | mergeability unknown",
+            status_line(&status(None, Some(false), Some(true)))
+        );
+        assert_eq!(
+            "pipeline passed | approval unknown | mergeable",
+            status_line(&RequestStatus {
+                mergeable: Some(true),
+                pipeline_green: Some(true),
+                ..Default::default()
+            })
+        );
+    }
+
+    #[test]
+    fn test_notable_changes() {
+        let running = status(Some(false), Some(false), Some(true));
+        let passed = status(Some(true), Some(true), Some(false));
+        assert_eq!(
+            vec!["the pipeline passed", "it's mergeable"],
+            notable_changes(&running, &passed)
+        );
+        let failed = status(Some(false), Some(false), Some(false));
+        assert_eq!(
+            vec!["the pipeline finished without passing"],
+            notable_changes(&running, &failed)
+        );
+        assert!(notable_changes(&passed, &passed).is_empty());
+        assert!(notable_changes(&failed, &running).is_empty());
+    }
+}